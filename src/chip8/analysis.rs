@@ -0,0 +1,159 @@
+use std::fmt;
+use crate::chip8::disasm;
+
+/// The instruction set a rom was written for, going by the opcodes it uses
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+/// What static analysis found out about a rom without running it
+pub struct Analysis {
+    pub platform: Platform,
+    /// bytes of the rom that are reachable by following jumps, calls and skips from the start
+    /// address. Jumps through `JP V0` can't be followed, so this is a lower bound.
+    pub reachable_bytes: usize,
+    /// `(address, opcode)` of every reachable opcode that no supported platform defines
+    pub invalid_opcodes: Vec<(u16, u16)>,
+    /// `(address, opcode)` of reachable opcodes from extensions crust8cean doesn't implement
+    pub unsupported_opcodes: Vec<(u16, u16)>,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Walks the control flow of `program` loaded at `start_addr`, decoding every instruction it can
+/// reach. Anything that isn't reached is assumed to be data.
+pub fn analyse(program: &[u8], start_addr: u16) -> Analysis {
+    let end_addr = start_addr as usize + program.len();
+    let word_at = |addr: usize| -> Option<u16> {
+        if addr < start_addr as usize || addr + 1 >= end_addr {
+            return None;
+        }
+        let offset = addr - start_addr as usize;
+        Some(((program[offset] as u16) << 8) | program[offset + 1] as u16)
+    };
+
+    let mut reachable = vec![false; program.len()];
+    let mut visited = vec![false; program.len()];
+    let mut analysis = Analysis {
+        platform: Platform::Chip8,
+        reachable_bytes: 0,
+        invalid_opcodes: Vec::new(),
+        unsupported_opcodes: Vec::new(),
+    };
+
+    let mut pending = vec![start_addr as usize];
+    while let Some(addr) = pending.pop() {
+        let opcode = match word_at(addr) {
+            Some(opcode) => opcode,
+            None => continue,
+        };
+        let offset = addr - start_addr as usize;
+        if visited[offset] {
+            continue;
+        }
+        visited[offset] = true;
+
+        let platform = opcode_platform(opcode);
+        let length = match platform {
+            // F000 nnnn loads a 16 bit address from the following word
+            Some(Platform::XoChip) if opcode == 0xF000 => 4,
+            _ => 2,
+        };
+        for byte in reachable.iter_mut().skip(offset).take(length) {
+            *byte = true;
+        }
+
+        let platform = match platform {
+            Some(platform) => platform,
+            None => {
+                analysis.invalid_opcodes.push((addr as u16, opcode));
+                continue;
+            },
+        };
+        if platform > analysis.platform {
+            analysis.platform = platform;
+        }
+        if platform != Platform::Chip8 && disasm::decode(opcode).is_none() {
+            analysis.unsupported_opcodes.push((addr as u16, opcode));
+        }
+
+        let next = addr + length;
+        match opcode & 0xF000 {
+            // RET, and SUPER-CHIP's EXIT
+            0x0000 if opcode == 0x00EE || opcode == 0x00FD => (),
+            0x1000 => pending.push((opcode & 0x0FFF) as usize),
+            0x2000 => {
+                pending.push((opcode & 0x0FFF) as usize);
+                pending.push(next);
+            },
+            // the target depends on V0
+            0xB000 => (),
+            0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xE000 => {
+                let skipped = if word_at(next) == Some(0xF000) { 4 } else { 2 };
+                pending.push(next);
+                pending.push(next + skipped);
+            },
+            _ => pending.push(next),
+        }
+    }
+
+    analysis.reachable_bytes = reachable.iter().filter(|byte| **byte).count();
+    analysis.invalid_opcodes.sort();
+    analysis.unsupported_opcodes.sort();
+    analysis
+}
+
+/// The first platform that defines `opcode`, or None if no platform does
+pub fn opcode_platform(opcode: u16) -> Option<Platform> {
+    // XO-CHIP's audio opcodes, which crust8cean runs too
+    if opcode == 0xF002 || opcode & 0xF0FF == 0xF03A {
+        return Some(Platform::XoChip);
+    }
+    if disasm::decode(opcode).is_some() {
+        // a zero height sprite draws nothing on CHIP-8, it draws a 16x16 sprite on SUPER-CHIP
+        if opcode & 0xF00F == 0xD000 {
+            return Some(Platform::SuperChip);
+        }
+        return Some(Platform::Chip8);
+    }
+
+    let nibbles = (
+        ((opcode & 0xF000) >> 12) as u8,
+        ((opcode & 0x0F00) >> 8) as u8,
+        ((opcode & 0x00F0) >> 4) as u8,
+        (opcode & 0x000F) as u8
+    );
+    match nibbles {
+        // SYS calls to machine code routines, only the original interpreter could run them
+        (0x00, _, _, _) if opcode & 0x0F00 != 0 => Some(Platform::Chip8),
+        (0x00, 0x00, 0x0C, _) |
+        (0x00, 0x00, 0x0F, 0x0B) |
+        (0x00, 0x00, 0x0F, 0x0C) |
+        (0x00, 0x00, 0x0F, 0x0D) |
+        (0x00, 0x00, 0x0F, 0x0E) |
+        (0x00, 0x00, 0x0F, 0x0F) |
+        (0x0F, _, 0x03, 0x00) |
+        (0x0F, _, 0x07, 0x05) |
+        (0x0F, _, 0x08, 0x05) => Some(Platform::SuperChip),
+        (0x00, 0x00, 0x0D, _) |
+        (0x05, _, _, 0x02) |
+        (0x05, _, _, 0x03) |
+        (0x0F, 0x00, 0x00, 0x00) |
+        (0x0F, _, 0x00, 0x01) |
+        (0x0F, 0x00, 0x00, 0x02) |
+        (0x0F, _, 0x03, 0x0A) => Some(Platform::XoChip),
+        _ => None,
+    }
+}
@@ -0,0 +1,78 @@
+/// Decodes a single opcode into its mnemonic, returns None if the interpreter doesn't
+/// recognize the opcode.
+pub fn decode(opcode: u16) -> Option<String> {
+    let nibbles = (
+        ((opcode & 0xF000) >> 12) as u8,
+        ((opcode & 0x0F00) >> 8) as u8,
+        ((opcode & 0x00F0) >> 4) as u8,
+        (opcode & 0x000F) as u8
+    );
+
+    let nnn = opcode & 0x0FFF;
+    let kk = (opcode & 0x00FF) as u8;
+    let x = nibbles.1;
+    let y = nibbles.2;
+    let n = nibbles.3;
+    let mnemonic = match nibbles {
+        (0x00, 0x00, 0x00, 0x00) => format!("SYS 0x{:03X}", nnn),
+        (0x00, 0x00, 0x0E, 0x00) => "CLS".to_string(),
+        (0x00, 0x00, 0x0E, 0x0E) => "RET".to_string(),
+        (0x01, _, _, _) => format!("JP 0x{:03X}", nnn),
+        (0x02, _, _, _) => format!("CALL 0x{:03X}", nnn),
+        (0x03, _, _, _) => format!("SE V{:X}, 0x{:02X}", x, kk),
+        (0x04, _, _, _) => format!("SNE V{:X}, 0x{:02X}", x, kk),
+        (0x05, _, _, 0x00) => format!("SE V{:X}, V{:X}", x, y),
+        (0x06, _, _, _) => format!("LD V{:X}, 0x{:02X}", x, kk),
+        (0x07, _, _, _) => format!("ADD V{:X}, 0x{:02X}", x, kk),
+        (0x08, _, _, 0x00) => format!("LD V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x01) => format!("OR V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x02) => format!("AND V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x03) => format!("XOR V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x04) => format!("ADD V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x05) => format!("SUB V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x06) => format!("SHR V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x07) => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x08, _, _, 0x0E) => format!("SHL V{:X}, V{:X}", x, y),
+        (0x09, _, _, 0x00) => format!("SNE V{:X}, V{:X}", x, y),
+        (0x0A, _, _, _) => format!("LD I, 0x{:03X}", nnn),
+        (0x0B, _, _, _) => format!("JP V0, 0x{:03X}", nnn),
+        (0x0C, _, _, _) => format!("RND V{:X}, 0x{:02X}", x, kk),
+        (0x0D, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0x0E, _, 0x09, 0x0E) => format!("SKP V{:X}", x),
+        (0x0E, _, 0x0A, 0x01) => format!("SKNP V{:X}", x),
+        (0x0F, 0x00, 0x00, 0x02) => "LD AUDIO, [I]".to_string(),
+        (0x0F, _, 0x00, 0x07) => format!("LD V{:X}, DT", x),
+        (0x0F, _, 0x00, 0x0A) => format!("LD V{:X}, K", x),
+        (0x0F, _, 0x01, 0x05) => format!("LD DT, V{:X}", x),
+        (0x0F, _, 0x01, 0x08) => format!("LD ST, V{:X}", x),
+        (0x0F, _, 0x01, 0x0E) => format!("ADD I, V{:X}", x),
+        (0x0F, _, 0x02, 0x09) => format!("LD F, V{:X}", x),
+        (0x0F, _, 0x03, 0x03) => format!("LD B, V{:X}", x),
+        (0x0F, _, 0x03, 0x0A) => format!("LD PITCH, V{:X}", x),
+        (0x0F, _, 0x05, 0x05) => format!("LD [I], V{:X}", x),
+        (0x0F, _, 0x06, 0x05) => format!("LD V{:X}, [I]", x),
+        _ => return None,
+    };
+
+    Some(mnemonic)
+}
+
+/// Disassembles a program loaded at `start_addr`, one line per 16 bit word. Words that don't
+/// decode to an instruction (usually sprite data) are emitted as raw data.
+pub fn disassemble(program: &[u8], start_addr: u16) -> Vec<String> {
+    program.chunks(2)
+        .enumerate()
+        .map(|(i, word)| {
+            let addr = start_addr as usize + i * 2;
+            if word.len() < 2 {
+                return format!("0x{:03X}: {:02X}    DB 0x{:02X}", addr, word[0], word[0]);
+            }
+
+            let opcode = ((word[0] as u16) << 8) | word[1] as u16;
+            match decode(opcode) {
+                Some(mnemonic) => format!("0x{:03X}: {:04X}  {}", addr, opcode, mnemonic),
+                None => format!("0x{:03X}: {:04X}  DW 0x{:04X}", addr, opcode, opcode),
+            }
+        })
+        .collect()
+}
@@ -1 +1,4 @@
-pub mod cpu;
\ No newline at end of file
+pub mod analysis;
+pub mod cpu;
+pub mod disasm;
+pub mod quirks;
\ No newline at end of file
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Behaviours that differ between CHIP-8 interpreters. Roms written for one interpreter often
+/// misbehave on another, so these can be toggled per rom.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    /// 8xy6/8xyE shift Vx in place instead of shifting Vy into Vx
+    pub shift: bool,
+    /// Fx55/Fx65 leave I untouched instead of incrementing it past the last register
+    pub load_store: bool,
+    /// Bnnn jumps to nnn + Vx (x being the high nibble of nnn) instead of nnn + V0
+    pub jump: bool,
+    /// 8xy1/8xy2/8xy3 reset VF to 0
+    pub vf_reset: bool,
+    /// sprites drawn past the edge of the screen are clipped instead of wrapping around
+    pub clipping: bool,
+}
+
+const QUIRK_NAMES: [&str; 5] = ["shift", "load_store", "jump", "vf_reset", "clipping"];
+const PRESET_NAMES: [&str; 3] = ["default", "chip8", "schip"];
+
+impl Quirks {
+    /// Quirks matching the original COSMAC VIP interpreter
+    pub fn chip8() -> Self {
+        Quirks {
+            shift: false,
+            load_store: false,
+            jump: false,
+            vf_reset: true,
+            clipping: true,
+        }
+    }
+
+    /// Quirks matching SUPER-CHIP 1.1 on the HP48
+    pub fn schip() -> Self {
+        Quirks {
+            shift: true,
+            load_store: true,
+            jump: true,
+            vf_reset: false,
+            clipping: true,
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Quirks::default()),
+            "chip8" | "vip" => Some(Quirks::chip8()),
+            "schip" | "superchip" => Some(Quirks::schip()),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, name: &str, value: bool) -> bool {
+        match name {
+            "shift" => self.shift = value,
+            "load_store" => self.load_store = value,
+            "jump" => self.jump = value,
+            "vf_reset" => self.vf_reset = value,
+            "clipping" => self.clipping = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Default for Quirks {
+    /// The behaviour crust8cean has always had
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            load_store: true,
+            jump: false,
+            vf_reset: false,
+            clipping: false,
+        }
+    }
+}
+
+/// Parses a comma separated list of presets and quirk names, applied left to right, e.g.
+/// `chip8,shift` or `schip,no-jump`.
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut quirks = Quirks::default();
+        for token in s.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            if let Some(preset) = Quirks::preset(token) {
+                quirks = preset;
+                continue;
+            }
+
+            let (name, value) = match token.strip_prefix("no-") {
+                Some(name) => (name, false),
+                None => (token, true),
+            };
+            if !quirks.set(name, value) {
+                return Err(format!("unknown quirk '{}', expected one of the presets {} or quirks {} \
+                                    (prefix a quirk with no- to disable it)",
+                                   token, PRESET_NAMES.join("/"), QUIRK_NAMES.join("/")));
+            }
+        }
+
+        Ok(quirks)
+    }
+}
+
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(preset) = PRESET_NAMES.iter().find(|name| Quirks::preset(name) == Some(*self)) {
+            return write!(f, "{}", preset);
+        }
+
+        let values = [self.shift, self.load_store, self.jump, self.vf_reset, self.clipping];
+        let names: Vec<String> = QUIRK_NAMES.iter().zip(values.iter())
+            .map(|(name, enabled)| if *enabled {
+                name.to_string()
+            } else {
+                format!("no-{}", name)
+            })
+            .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
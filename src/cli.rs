@@ -0,0 +1,173 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use crust8cean::modules::config::{Config, ConfigSources};
+use crust8cean::modules::database::RomInfo;
+
+pub fn build_app() -> App<'static, 'static> {
+    let rom = Arg::with_name("ROM")
+        .help("path to the rom to load, or archive.zip:NAME to load NAME from a zip archive")
+        .required(true)
+        .index(1);
+    let cycles = Arg::with_name("cycles")
+        .long("cycles")
+        .value_name("N")
+        .help("number of instructions to run before stopping");
+    let patch = Arg::with_name("patch")
+        .long("patch")
+        .value_name("PATH")
+        .help("IPS or BPS patch to apply to the rom, instead of a .ips or .bps named like the rom next to it");
+    let script = Arg::with_name("script")
+        .long("script")
+        .value_name("PATH")
+        .help("run a Rhai script alongside the rom, see the README for what scripts can do");
+
+    App::new("crust8cean")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("yet another™ CHIP-8 emulator")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(Arg::with_name("config")
+            .long("config")
+            .value_name("PATH")
+            .global(true)
+            .help("project config file to read instead of ./config.toml"))
+        .arg(Arg::with_name("scale")
+            .long("scale")
+            .value_name("N")
+            .global(true)
+            .help("initial window scale, from 1 to 32"))
+        .arg(Arg::with_name("grid")
+            .long("grid")
+            .global(true)
+            .help("draw grid lines between pixels"))
+        .arg(Arg::with_name("color")
+            .long("color")
+            .value_name("RRGGBB")
+            .global(true)
+            .help("pixel color as hex, e.g. FF8000, overriding the palette"))
+        .arg(Arg::with_name("palette")
+            .long("palette")
+            .value_name("NAME")
+            .global(true)
+            .help("initial palette, e.g. white, amber, gameboy or the name of a theme file"))
+        .arg(Arg::with_name("persistence")
+            .long("persistence")
+            .value_name("MODE")
+            .global(true)
+            .possible_values(&["off", "decay", "or"])
+            .help("keep pixels lit after they're turned off to reduce flicker"))
+        .arg(Arg::with_name("terminal")
+            .long("terminal")
+            .global(true)
+            .help("draw the screen in the terminal and read keys from it instead of opening a window"))
+        .arg(Arg::with_name("no-sound")
+            .long("no-sound")
+            .global(true)
+            .help("disable sound"))
+        .arg(Arg::with_name("no-render")
+            .long("no-render")
+            .global(true)
+            .help("don't draw the screen"))
+        .arg(Arg::with_name("debug")
+            .long("debug")
+            .global(true)
+            .help("print the register state after every instruction and take debugger commands on stdin"))
+        .arg(Arg::with_name("quirks")
+            .long("quirks")
+            .value_name("QUIRKS")
+            .global(true)
+            .help("comma separated quirk presets (default/chip8/schip) and quirks \
+                   (shift/load_store/jump/vf_reset/clipping, prefix with no- to disable)"))
+        .arg(Arg::with_name("ips")
+            .long("ips")
+            .value_name("N")
+            .global(true)
+            .help("instructions to run per second"))
+        .arg(Arg::with_name("seed")
+            .long("seed")
+            .value_name("N")
+            .global(true)
+            .help("seed for the random number generator used by RND"))
+        .subcommand(SubCommand::with_name("run")
+            .about("run a rom in a window")
+            .arg(rom.clone())
+            .arg(patch.clone())
+            .arg(script.clone()))
+        .subcommand(SubCommand::with_name("disasm")
+            .about("print the disassembly of a rom")
+            .arg(rom.clone())
+            .arg(patch.clone()))
+        .subcommand(SubCommand::with_name("trace")
+            .about("run a rom without a window, printing every instruction executed")
+            .arg(rom.clone())
+            .arg(cycles.clone().default_value("1000"))
+            .arg(patch.clone())
+            .arg(script.clone())
+            .arg(Arg::with_name("screenshot")
+                .long("screenshot")
+                .help("save a screenshot of the screen when the trace stops"))
+            .arg(Arg::with_name("wav")
+                .long("wav")
+                .value_name("PATH")
+                .help("render the beeper to a wav, one frame of samples per frame of instructions")))
+        .subcommand(SubCommand::with_name("bench")
+            .about("run a rom without a window as fast as possible and report the speed")
+            .arg(rom.clone())
+            .arg(patch.clone())
+            .arg(cycles.default_value("1000000")))
+        .subcommand(SubCommand::with_name("info")
+            .about("print information about a rom")
+            .arg(rom)
+            .arg(patch))
+        .subcommand(SubCommand::with_name("list")
+            .about("list the roms in a zip archive")
+            .arg(Arg::with_name("ARCHIVE")
+                .help("path to the zip archive")
+                .required(true)
+                .index(1)))
+        .subcommand(SubCommand::with_name("config")
+            .about("inspect the configuration")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("dump")
+                .about("print the effective config after merging every layer")
+                .arg(Arg::with_name("ROM")
+                    .help("include the profile for this rom")
+                    .index(1))))
+}
+
+/// Loads the layered config for `rom_path`, with any overrides passed on the command line
+/// applied on top.
+pub fn load_config(matches: &ArgMatches, rom_path: Option<&str>, rom_info: Option<&RomInfo>)
+    -> Result<Config, String> {
+    let mut overrides = Vec::new();
+    let values = [("scale", "display.scale"), ("color", "display.color"), ("palette", "display.palette"), ("persistence", "display.persistence"),
+        ("quirks", "cpu.quirks"), ("ips", "cpu.instructions_per_second"), ("seed", "cpu.seed")];
+    for (arg, key) in values.iter() {
+        if let Some(value) = matches.value_of(arg) {
+            overrides.push((*key, value.to_string()));
+        }
+    }
+
+    let flags = [("no-sound", "audio.enabled", false), ("no-render", "display.render", false),
+        ("debug", "cpu.debug", true), ("grid", "display.grid", true)];
+    for (arg, key, value) in flags.iter() {
+        if matches.is_present(arg) {
+            overrides.push((*key, value.to_string()));
+        }
+    }
+    if matches.is_present("terminal") {
+        overrides.push(("display.backend", "terminal".to_string()));
+    }
+
+    Config::load(&ConfigSources {
+        project: matches.value_of("config"),
+        rom: rom_path,
+        rom_info,
+        overrides,
+    })
+}
+
+pub fn parse_arg<T>(name: &str, value: &str) -> Result<T, String>
+    where T: std::str::FromStr, T::Err: std::fmt::Display {
+    value.parse::<T>()
+        .map_err(|e| format!("invalid value '{}' for --{}: {}", value, name, e))
+}
@@ -0,0 +1,320 @@
+//! A reinforcement learning environment over `Machine`, in the style of Gym: `reset` starts an
+//! episode and `step` holds some keys for some frames, handing back the screen, a reward read
+//! from memory and whether the episode is over.
+//!
+//! ```no_run
+//! use crust8cean::Inputs;
+//! use crust8cean::env::{Env, ScoreReward};
+//!
+//! let rom = std::fs::read("game.ch8").unwrap();
+//! // the score is kept as 3 BCD digits at 0x3F0, the lives at 0x3F3
+//! let reward = ScoreReward::bcd(0x3F0, 3).with_lives(0x3F3);
+//! let mut env = Env::new(&rom, Default::default(), Box::new(reward)).unwrap();
+//! env.reset(42);
+//! loop {
+//!     // hold key 4 for 4 frames, then learn from step.observation and step.reward
+//!     let step = env.step(&Inputs::with_keys(&[0x4]), 4);
+//!     if step.done {
+//!         break;
+//!     }
+//! }
+//! ```
+//!
+//! `EnvBatch` runs a batch of environments side by side, each in a thread of its own.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use crate::machine::{Inputs, Machine, Options};
+
+/// Reads the reward for each step from memory. Closures from memory to a reward work as
+/// rewards too.
+pub trait Reward: Send {
+    /// Called with the memory the rom starts with at every reset
+    fn reset(&mut self, _memory: &[u8]) {}
+
+    /// The reward for a step, given the memory at the end of it
+    fn reward(&mut self, memory: &[u8]) -> f32;
+
+    /// Whether the episode is over, e.g. out of lives. Episodes also end when the rom stops.
+    fn done(&mut self, _memory: &[u8]) -> bool {
+        false
+    }
+}
+
+impl<F> Reward for F where F: FnMut(&[u8]) -> f32 + Send {
+    fn reward(&mut self, memory: &[u8]) -> f32 {
+        self(memory)
+    }
+}
+
+/// How a number is kept in memory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Byte,
+    /// two bytes, most significant first
+    Word,
+    /// a decimal digit a byte, most significant first, the way Fx33 stores them
+    Bcd(usize),
+}
+
+impl Encoding {
+    fn read(self, memory: &[u8], address: u16) -> u32 {
+        let address = address as usize;
+        let byte = |offset: usize| memory.get(address + offset).cloned().unwrap_or(0) as u32;
+        match self {
+            Encoding::Byte => byte(0),
+            Encoding::Word => byte(0) << 8 | byte(1),
+            Encoding::Bcd(digits) => (0..digits).fold(0, |value, digit| value * 10 + byte(digit)),
+        }
+    }
+}
+
+/// Rewards the change in a score kept in memory since the last step, optionally ending the
+/// episode once the lives run out
+#[derive(Debug, Clone)]
+pub struct ScoreReward {
+    address: u16,
+    encoding: Encoding,
+    lives: Option<u16>,
+    last: u32,
+}
+
+impl ScoreReward {
+    pub fn new(address: u16, encoding: Encoding) -> Self {
+        ScoreReward {
+            address,
+            encoding,
+            lives: None,
+            last: 0,
+        }
+    }
+
+    /// A score of `digits` BCD digits starting at `address`
+    pub fn bcd(address: u16, digits: usize) -> Self {
+        ScoreReward::new(address, Encoding::Bcd(digits))
+    }
+
+    /// Ends the episode when the byte at `address` gets to 0
+    pub fn with_lives(mut self, address: u16) -> Self {
+        self.lives = Some(address);
+        self
+    }
+}
+
+impl Reward for ScoreReward {
+    fn reset(&mut self, memory: &[u8]) {
+        self.last = self.encoding.read(memory, self.address);
+    }
+
+    fn reward(&mut self, memory: &[u8]) -> f32 {
+        let score = self.encoding.read(memory, self.address);
+        let reward = score as f32 - self.last as f32;
+        self.last = score;
+        reward
+    }
+
+    fn done(&mut self, memory: &[u8]) -> bool {
+        self.lives.is_some_and(|address| Encoding::Byte.read(memory, address) == 0)
+    }
+}
+
+/// What a step did
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// the framebuffer at the end of the step, `WIDTH` by `HEIGHT` bytes, 0 for off
+    pub observation: Vec<u8>,
+    pub reward: f32,
+    /// whether the episode is over, stepping again does nothing until `reset`
+    pub done: bool,
+}
+
+/// A rom to train on, see the module docs
+pub struct Env {
+    machine: Machine,
+    reward: Box<dyn Reward>,
+    max_frames: Option<u64>,
+    frames: u64,
+    done: bool,
+}
+
+impl Env {
+    /// Loads `rom` with `options`, ready to step. Call `reset` to pick the seed.
+    pub fn new(rom: &[u8], options: Options, mut reward: Box<dyn Reward>) -> Result<Self, String> {
+        let machine = Machine::new(rom, options)?;
+        reward.reset(machine.memory());
+        Ok(Env {
+            machine,
+            reward,
+            max_frames: None,
+            frames: 0,
+            done: false,
+        })
+    }
+
+    /// Ends episodes after `frames` frames, however the rom is doing
+    pub fn set_max_frames(&mut self, frames: Option<u64>) {
+        self.max_frames = frames;
+    }
+
+    /// Starts a new episode from the beginning of the rom with RND seeded with `seed`,
+    /// returning the first observation
+    pub fn reset(&mut self, seed: u64) -> Vec<u8> {
+        self.machine.reset(Some(seed));
+        self.reward.reset(self.machine.memory());
+        self.frames = 0;
+        self.done = false;
+        self.observation()
+    }
+
+    /// Holds `action` for `frames` frames, stopping early if the episode ends
+    pub fn step(&mut self, action: &Inputs, frames: u32) -> Step {
+        if self.done {
+            return Step {
+                observation: self.observation(),
+                reward: 0.0,
+                done: true,
+            };
+        }
+
+        for _ in 0..frames {
+            let output = self.machine.run_frame(action);
+            self.frames += 1;
+            if !output.running || self.max_frames.is_some_and(|max| self.frames >= max) {
+                self.done = true;
+                break;
+            }
+        }
+        let reward = self.reward.reward(self.machine.memory());
+        self.done |= self.reward.done(self.machine.memory());
+        Step {
+            observation: self.observation(),
+            reward,
+            done: self.done,
+        }
+    }
+
+    /// The machine running the rom, e.g. to read more of its memory
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    fn observation(&self) -> Vec<u8> {
+        self.machine.framebuffer().to_vec()
+    }
+}
+
+enum Command {
+    Reset(u64),
+    Step(Inputs, u32),
+}
+
+struct Worker {
+    commands: Sender<Command>,
+    steps: Receiver<Step>,
+    thread: JoinHandle<()>,
+}
+
+/// Independent environments on the same rom, each with its own reward and a thread to run in,
+/// so a batch steps in about the time one environment takes
+pub struct EnvBatch {
+    workers: Vec<Worker>,
+}
+
+impl EnvBatch {
+    /// Starts an environment for each of `rewards`
+    pub fn new(rom: &[u8], options: Options, rewards: Vec<Box<dyn Reward>>) -> Result<Self, String> {
+        let mut workers = Vec::with_capacity(rewards.len());
+        for reward in rewards {
+            let rom = rom.to_vec();
+            let options = options.clone();
+            let (commands, command_receiver) = mpsc::channel();
+            let (step_sender, steps) = mpsc::channel();
+            let (ready, started) = mpsc::channel();
+            // machines can't move between threads, so each is made in the thread it runs in
+            let thread = thread::spawn(move || {
+                let mut env = match Env::new(&rom, options, reward) {
+                    Ok(env) => env,
+                    Err(e) => {
+                        let _ = ready.send(Err(e));
+                        return;
+                    },
+                };
+                let _ = ready.send(Ok(()));
+                for command in command_receiver {
+                    let step = match command {
+                        Command::Reset(seed) => Step {
+                            observation: env.reset(seed),
+                            reward: 0.0,
+                            done: false,
+                        },
+                        Command::Step(action, frames) => env.step(&action, frames),
+                    };
+                    if step_sender.send(step).is_err() {
+                        break;
+                    }
+                }
+            });
+            started.recv().map_err(|_| "environment thread stopped".to_string())??;
+            workers.push(Worker { commands, steps, thread });
+        }
+        Ok(EnvBatch { workers })
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    /// Resets each environment with its seed from `seeds`, returning their first observations
+    pub fn reset(&mut self, seeds: &[u64]) -> Result<Vec<Vec<u8>>, String> {
+        if seeds.len() != self.len() {
+            return Err(format!("expected a seed for each of the {} environments, got {}", self.len(), seeds.len()));
+        }
+        let commands = seeds.iter().map(|seed| Command::Reset(*seed)).collect();
+        Ok(self.run(commands)?.into_iter().map(|step| step.observation).collect())
+    }
+
+    /// Steps each environment with its action from `actions` for `frames` frames, all at once
+    pub fn step(&mut self, actions: &[Inputs], frames: u32) -> Result<Vec<Step>, String> {
+        if actions.len() != self.len() {
+            return Err(format!("expected an action for each of the {} environments, got {}", self.len(), actions.len()));
+        }
+        let commands = actions.iter().map(|action| Command::Step(*action, frames)).collect();
+        self.run(commands)
+    }
+
+    /// Hands each worker its command and waits for every step. A worker whose thread stopped,
+    /// i.e. whose reward panicked, fails the whole batch, the others still finish their step so
+    /// they stay in sync.
+    fn run(&mut self, commands: Vec<Command>) -> Result<Vec<Step>, String> {
+        for (worker, command) in self.workers.iter().zip(commands) {
+            // a stopped worker's step fails to arrive below
+            let _ = worker.commands.send(command);
+        }
+        let mut steps = Vec::with_capacity(self.workers.len());
+        let mut stopped = None;
+        for (index, worker) in self.workers.iter().enumerate() {
+            match worker.steps.recv() {
+                Ok(step) => steps.push(step),
+                Err(_) => stopped = stopped.or(Some(index)),
+            }
+        }
+        match stopped {
+            Some(index) => Err(format!("environment {} stopped, its thread panicked", index)),
+            None => Ok(steps),
+        }
+    }
+}
+
+impl Drop for EnvBatch {
+    fn drop(&mut self) {
+        for worker in self.workers.drain(..) {
+            // hanging up tells the thread to finish
+            drop(worker.commands);
+            let _ = worker.thread.join();
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! crust8cean's CHIP-8 interpreter as a library, for embedding it in other programs.
+//!
+//! `Machine` is the API to build on. It runs a rom a frame at a time without a window, input
+//! or sound of its own, handing back the screen and the beeper's samples instead:
+//!
+//! ```no_run
+//! use crust8cean::{Inputs, Machine, Options};
+//!
+//! let rom = std::fs::read("game.ch8").unwrap();
+//! let mut machine = Machine::new(&rom, Options::default()).unwrap();
+//! loop {
+//!     // hold key 5 this frame
+//!     let output = machine.run_frame(&Inputs::with_keys(&[0x5]));
+//!     if output.screen_changed {
+//!         // draw machine.framebuffer(), 64x32 bytes with 0 for off
+//!     }
+//!     // play output.samples, SAMPLES_PER_FRAME at SAMPLE_RATE
+//!     if !output.running {
+//!         break;
+//!     }
+//! }
+//! ```
+//!
+//! `env` wraps machines up as reinforcement learning environments.
+//!
+//! `chip8` and `modules` are the interpreter's internals, shared with the `crust8cean` binary.
+//! They change whenever the interpreter does.
+
+extern crate rand;
+#[cfg(feature = "desktop")]
+extern crate minifb;
+#[cfg(feature = "desktop")]
+extern crate ears;
+extern crate config;
+extern crate serde;
+extern crate toml;
+extern crate dirs;
+extern crate serde_json;
+extern crate sha1;
+extern crate zip;
+extern crate crc32fast;
+extern crate png;
+extern crate gif;
+extern crate hound;
+#[cfg(feature = "desktop")]
+extern crate crossterm;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(feature = "scripting")]
+extern crate rhai;
+
+#[doc(hidden)]
+pub mod chip8;
+#[doc(hidden)]
+pub mod modules;
+mod machine;
+pub mod env;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+mod tests;
+
+pub use machine::{FrameOutput, Inputs, Machine, Options, Waveform, FRAME_RATE, HEIGHT, KEYS, SAMPLE_RATE,
+                  SAMPLES_PER_FRAME, WIDTH};
+pub use chip8::quirks::Quirks;
@@ -0,0 +1,490 @@
+//! The libretro API around `Machine`, so crust8cean runs as a core in RetroArch and other
+//! libretro frontends. Built with `--no-default-features --features libretro`, see the README.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+use crate::chip8::cpu::STATE_SIZE;
+use crate::chip8::quirks::Quirks;
+use crate::machine::{Inputs, Machine, Options, FRAME_RATE, HEIGHT, KEYS, SAMPLE_RATE, WIDTH};
+use crate::modules::input::DEFAULT_KEYS;
+use crate::modules::menu::{QUIRK_PRESETS, SPEEDS};
+use crate::modules::palette::{self, DEFAULT_PALETTE};
+
+const API_VERSION: c_uint = 1;
+
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+const ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const DEVICE_JOYPAD: c_uint = 1;
+const DEVICE_KEYBOARD: c_uint = 3;
+const MEMORY_SYSTEM_RAM: c_uint = 2;
+const REGION_NTSC: c_uint = 0;
+
+// joypad buttons, the CHIP-8 key each presses and what the frontend shows for it. Most games
+// move with 2/4/6/8 and act with 5.
+const JOYPAD_KEYS: [(c_uint, u8, &[u8]); KEYS] = [
+    (4, 0x2, b"2 (up)\0"),
+    (5, 0x8, b"8 (down)\0"),
+    (6, 0x4, b"4 (left)\0"),
+    (7, 0x6, b"6 (right)\0"),
+    (8, 0x5, b"5\0"),
+    (0, 0x0, b"0\0"),
+    (9, 0x1, b"1\0"),
+    (1, 0x3, b"3\0"),
+    (10, 0x7, b"7\0"),
+    (11, 0x9, b"9\0"),
+    (12, 0xA, b"A\0"),
+    (13, 0xB, b"B\0"),
+    (14, 0xC, b"C\0"),
+    (15, 0xD, b"D\0"),
+    (2, 0xE, b"E\0"),
+    (3, 0xF, b"F\0"),
+];
+
+const QUIRKS_KEY: &[u8] = b"crust8cean_quirks\0";
+const SPEED_KEY: &[u8] = b"crust8cean_speed\0";
+const PALETTE_KEY: &[u8] = b"crust8cean_palette\0";
+
+/// retro_system_info
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+/// retro_game_geometry
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+/// retro_system_timing
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+/// retro_system_av_info
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+/// retro_game_info
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+/// retro_variable
+#[repr(C)]
+pub struct Variable {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+/// retro_input_descriptor
+#[repr(C)]
+pub struct InputDescriptor {
+    pub port: c_uint,
+    pub device: c_uint,
+    pub index: c_uint,
+    pub id: c_uint,
+    pub description: *const c_char,
+}
+
+pub type EnvironmentFn = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn = extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = extern "C" fn();
+pub type InputStateFn = extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+/// Everything the core keeps between calls. libretro is a C API of free functions called from
+/// one thread, so it lives in a thread local.
+struct Core {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+    // the core options' "Description; value|value" strings, kept alive for the frontend
+    variables: Vec<(CString, CString)>,
+    machine: Option<Machine>,
+    foreground: u32,
+    background: u32,
+    pixels: Vec<u32>,
+    audio: Vec<i16>,
+}
+
+thread_local! {
+    static CORE: RefCell<Core> = RefCell::new(Core {
+        environment: None,
+        video_refresh: None,
+        audio_sample_batch: None,
+        input_poll: None,
+        input_state: None,
+        variables: Vec::new(),
+        machine: None,
+        foreground: 0xFFFFFF,
+        background: 0x000000,
+        pixels: Vec::new(),
+        audio: Vec::new(),
+    });
+}
+
+/// Runs `f` on the core, giving back `default` if it panics. Roms that can't run stop the cpu
+/// with an error rather than panicking, so a panic is a bug, but unwinding into the frontend
+/// would abort it along with the player's session.
+fn with_core<T>(default: T, f: impl FnOnce(&mut Core) -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(|| CORE.with(|core| f(&mut core.borrow_mut()))))
+        .unwrap_or(default)
+}
+
+impl Core {
+    fn environment(&self, cmd: c_uint, data: *mut c_void) -> bool {
+        self.environment.is_some_and(|environment| environment(cmd, data))
+    }
+
+    /// The value the frontend has for a core option
+    fn variable(&self, key: &[u8]) -> Option<String> {
+        let mut variable = Variable {
+            key: key.as_ptr() as *const c_char,
+            value: ptr::null(),
+        };
+        if !self.environment(ENVIRONMENT_GET_VARIABLE, &mut variable as *mut Variable as *mut c_void)
+            || variable.value.is_null() {
+            return None;
+        }
+        Some(unsafe { CStr::from_ptr(variable.value) }.to_string_lossy().into_owned())
+    }
+
+    /// Options from the quirks and speed core options, which apply when a game is loaded
+    fn options(&self) -> Options {
+        let mut options = Options::default();
+        if let Some(quirks) = self.variable(QUIRKS_KEY).and_then(|name| Quirks::preset(&name)) {
+            options.quirks = quirks;
+        }
+        if let Some(speed) = self.variable(SPEED_KEY).and_then(|speed| speed.parse().ok()) {
+            options.instructions_per_second = speed;
+        }
+        options
+    }
+
+    /// Picks up the palette core option, which applies straight away
+    fn update_palette(&mut self) {
+        let name = self.variable(PALETTE_KEY).unwrap_or_else(|| DEFAULT_PALETTE.to_string());
+        if let Some(palette) = palette::builtin().into_iter().find(|palette| palette.name == name) {
+            self.foreground = palette.foreground;
+            self.background = palette.background;
+        }
+    }
+
+    /// The CHIP-8 keys held on the first joypad or the keyboard, which uses the same keys as
+    /// the crust8cean binary's defaults
+    fn read_inputs(&self) -> Inputs {
+        let mut inputs = Inputs::default();
+        if let Some(input_state) = self.input_state {
+            for (id, key, _) in JOYPAD_KEYS.iter() {
+                inputs.keys[*key as usize] |= input_state(0, DEVICE_JOYPAD, 0, *id) != 0;
+            }
+            for (key, name) in DEFAULT_KEYS.iter().enumerate() {
+                // libretro's keyboard codes are lowercase ascii for letters and digits
+                let code = name.to_ascii_lowercase().as_bytes()[0] as c_uint;
+                inputs.keys[key] |= input_state(0, DEVICE_KEYBOARD, 0, code) != 0;
+            }
+        }
+        inputs
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(environment: EnvironmentFn) {
+    with_core((), |core| set_environment(core, environment))
+}
+
+fn set_environment(core: &mut Core, environment: EnvironmentFn) {
+    core.environment = Some(environment);
+
+    // the first value is the default
+    let speeds = Some(Options::default().instructions_per_second).into_iter()
+        .chain(SPEEDS.iter().cloned().filter(|speed| *speed != Options::default().instructions_per_second))
+        .map(|speed| speed.to_string())
+        .collect::<Vec<String>>();
+    let palettes = palette::builtin().into_iter()
+        .map(|palette| palette.name)
+        .collect::<Vec<String>>();
+    let options = [
+        (QUIRKS_KEY, format!("Quirks (restart); {}", QUIRK_PRESETS.join("|"))),
+        (SPEED_KEY, format!("Instructions per second (restart); {}", speeds.join("|"))),
+        (PALETTE_KEY, format!("Palette; {}", palettes.join("|"))),
+    ];
+    core.variables = options.iter()
+        .map(|(key, value)| (CStr::from_bytes_with_nul(key).unwrap().to_owned(), CString::new(value.as_str()).unwrap()))
+        .collect();
+
+    let mut variables: Vec<Variable> = core.variables.iter()
+        .map(|(key, value)| Variable { key: key.as_ptr(), value: value.as_ptr() })
+        .collect();
+    variables.push(Variable { key: ptr::null(), value: ptr::null() });
+    core.environment(ENVIRONMENT_SET_VARIABLES, variables.as_mut_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: VideoRefreshFn) {
+    with_core((), |core| core.video_refresh = Some(video_refresh))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_audio_sample: AudioSampleFn) {
+    // samples go to the frontend a frame at a time through the batch callback
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: AudioSampleBatchFn) {
+    with_core((), |core| core.audio_sample_batch = Some(audio_sample_batch))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: InputPollFn) {
+    with_core((), |core| core.input_poll = Some(input_poll))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: InputStateFn) {
+    with_core((), |core| core.input_state = Some(input_state))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    with_core((), |core| core.machine = None)
+}
+
+/// # Safety
+///
+/// `info` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: b"crust8cean\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+///
+/// `info` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: SystemTiming {
+            fps: FRAME_RATE as f64,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core((), |core| if let Some(machine) = core.machine.as_mut() {
+        machine.reset(None);
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    with_core((), run)
+}
+
+fn run(core: &mut Core) {
+    let mut updated = false;
+    if core.environment(ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void) && updated {
+        core.update_palette();
+    }
+    if let Some(input_poll) = core.input_poll {
+        input_poll();
+    }
+    let inputs = core.read_inputs();
+
+    let machine = match core.machine.as_mut() {
+        Some(machine) => machine,
+        None => return,
+    };
+    let was_running = machine.is_running();
+    let output = machine.run_frame(&inputs);
+    if was_running && !output.running {
+        if let Some(e) = machine.error() {
+            eprintln!("error: rom stopped: {}", e);
+        }
+    }
+
+    let (foreground, background) = (core.foreground, core.background);
+    core.pixels.clear();
+    core.pixels.extend(machine.framebuffer().iter().map(|pixel| if *pixel != 0 { foreground } else { background }));
+    if let Some(video_refresh) = core.video_refresh {
+        video_refresh(core.pixels.as_ptr() as *const c_void, WIDTH as c_uint, HEIGHT as c_uint, WIDTH * 4);
+    }
+
+    // libretro wants interleaved stereo
+    core.audio.clear();
+    core.audio.extend(output.samples.iter().flat_map(|sample| vec![*sample, *sample]));
+    if let Some(audio_sample_batch) = core.audio_sample_batch {
+        audio_sample_batch(core.audio.as_ptr(), output.samples.len());
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    STATE_SIZE
+}
+
+/// # Safety
+///
+/// `data` must be valid for writes of `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    with_core(false, |core| match core.machine.as_ref() {
+        Some(machine) if size >= STATE_SIZE => {
+            let state = machine.save_state();
+            ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+            true
+        },
+        _ => false,
+    })
+}
+
+/// # Safety
+///
+/// `data` must be valid for reads of `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    with_core(false, |core| match core.machine.as_mut() {
+        Some(machine) => {
+            let state = slice::from_raw_parts(data as *const u8, size.min(STATE_SIZE));
+            machine.load_state(state).map_err(|e| eprintln!("error: {}", e)).is_ok()
+        },
+        None => false,
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+///
+/// `game` must be null or point to a `GameInfo` whose `data` is null or valid for reads of
+/// `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size);
+    with_core(false, |core| load_game(core, rom))
+}
+
+fn load_game(core: &mut Core, rom: &[u8]) -> bool {
+    let mut format = PIXEL_FORMAT_XRGB8888;
+    if !core.environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+        eprintln!("error: the frontend doesn't support XRGB8888");
+        return false;
+    }
+    let mut descriptors: Vec<InputDescriptor> = JOYPAD_KEYS.iter()
+        .map(|(id, _, description)| InputDescriptor {
+            port: 0,
+            device: DEVICE_JOYPAD,
+            index: 0,
+            id: *id,
+            description: description.as_ptr() as *const c_char,
+        })
+        .collect();
+    descriptors.push(InputDescriptor { port: 0, device: 0, index: 0, id: 0, description: ptr::null() });
+    core.environment(ENVIRONMENT_SET_INPUT_DESCRIPTORS, descriptors.as_mut_ptr() as *mut c_void);
+
+    core.update_palette();
+    match Machine::new(rom, core.options()) {
+        Ok(machine) => {
+            core.machine = Some(machine);
+            true
+        },
+        Err(e) => {
+            eprintln!("error: {}", e);
+            false
+        },
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const GameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    with_core((), |core| core.machine = None)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    REGION_NTSC
+}
+
+/// Memory for cheats and achievements, valid until the game is unloaded
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    with_core(ptr::null_mut(), |core| match core.machine.as_mut() {
+        Some(machine) if id == MEMORY_SYSTEM_RAM => machine.memory_mut().as_mut_ptr() as *mut c_void,
+        _ => ptr::null_mut(),
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    with_core(0, |core| match core.machine.as_ref() {
+        Some(machine) if id == MEMORY_SYSTEM_RAM => machine.memory().len(),
+        _ => 0,
+    })
+}
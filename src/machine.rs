@@ -0,0 +1,197 @@
+use crate::chip8::cpu::{Cpu, FRAMES_PER_SECOND};
+use crate::chip8::quirks::Quirks;
+use crate::modules::audio::{Beeper, Tone};
+use crate::modules::config::{AudioConfig, Config, CpuConfig};
+use crate::modules::display::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::modules::input::KEYBOARD_SIZE;
+
+pub use crate::modules::audio::{Waveform, SAMPLE_RATE, SAMPLES_PER_FRAME};
+
+/// Width of the framebuffer in pixels
+pub const WIDTH: usize = SCREEN_WIDTH as usize;
+/// Height of the framebuffer in pixels
+pub const HEIGHT: usize = SCREEN_HEIGHT as usize;
+/// Frames `Machine::run_frame` should be called at per second, the rate the timers tick at
+pub const FRAME_RATE: u32 = FRAMES_PER_SECOND;
+/// Keys on the CHIP-8 keypad, 0 to F
+pub const KEYS: usize = KEYBOARD_SIZE;
+
+/// How a `Machine` runs its rom
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// which of the ways interpreters disagree on opcodes to follow
+    pub quirks: Quirks,
+    pub instructions_per_second: u32,
+    /// seed for RND, a random one when `None`
+    pub seed: Option<u64>,
+    /// how the beeper sounds in `FrameOutput::samples`
+    pub waveform: Waveform,
+    pub frequency: f32,
+    pub volume: f32,
+    pub attack_ms: u16,
+    pub release_ms: u16,
+}
+
+impl Default for Options {
+    /// The same defaults as the `crust8cean` binary
+    fn default() -> Self {
+        let cpu = CpuConfig::default();
+        let audio = AudioConfig::default();
+        Options {
+            quirks: cpu.quirks,
+            instructions_per_second: cpu.instructions_per_second,
+            seed: cpu.seed,
+            waveform: audio.waveform,
+            frequency: audio.frequency,
+            volume: audio.volume,
+            attack_ms: audio.attack_ms,
+            release_ms: audio.release_ms,
+        }
+    }
+}
+
+impl Options {
+    fn to_config(&self) -> Config {
+        let mut config = Config::default();
+        config.display.render = false;
+        config.display.osd = false;
+        config.cpu.quirks = self.quirks;
+        config.cpu.instructions_per_second = self.instructions_per_second;
+        config.cpu.seed = self.seed;
+        // the machine hands its samples back instead of playing them
+        config.audio = AudioConfig {
+            enabled: false,
+            waveform: self.waveform,
+            frequency: self.frequency,
+            volume: self.volume,
+            attack_ms: self.attack_ms,
+            release_ms: self.release_ms,
+        };
+        config
+    }
+}
+
+/// The keys held during a frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Inputs {
+    /// indexed by CHIP-8 key, 0 to F
+    pub keys: [bool; KEYS],
+}
+
+impl Inputs {
+    /// Inputs with the CHIP-8 keys in `keys` held, keys above F are ignored
+    pub fn with_keys(keys: &[u8]) -> Self {
+        let mut inputs = Inputs::default();
+        for key in keys.iter().filter(|key| (**key as usize) < KEYS) {
+            inputs.keys[*key as usize] = true;
+        }
+        inputs
+    }
+}
+
+/// What happened during a frame from `Machine::run_frame`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameOutput {
+    /// whether the sound timer ran during the frame
+    pub audio_active: bool,
+    /// `SAMPLES_PER_FRAME` mono samples at `SAMPLE_RATE` of the beeper
+    pub samples: Vec<i16>,
+    /// whether any pixels changed, i.e. the framebuffer needs drawing again
+    pub screen_changed: bool,
+    /// false once the rom has stopped, e.g. by jumping to itself forever or on an error, see
+    /// `Machine::error`
+    pub running: bool,
+}
+
+/// A CHIP-8 machine running a rom, without a window, input or sound of its own. Call
+/// `run_frame` `FRAME_RATE` times a second with the keys held, draw `framebuffer` and play
+/// the samples returned.
+pub struct Machine {
+    cpu: Cpu,
+    beeper: Beeper,
+    audio: AudioConfig,
+    // the state right after loading, for `reset`
+    initial_state: Vec<u8>,
+}
+
+impl Machine {
+    /// Loads `rom` at 0x200, failing if it doesn't fit in memory or `options` are invalid
+    pub fn new(rom: &[u8], options: Options) -> Result<Self, String> {
+        let config = options.to_config();
+        // the rest of the config is the display's and the keyboard's, which machines don't have
+        config.cpu.validate()?;
+        config.audio.validate()?;
+        Cpu::check_program(rom)?;
+        let audio = config.audio.clone();
+        let cpu = Cpu::new_headless(rom, config);
+        Ok(Machine {
+            beeper: Beeper::new(&audio),
+            audio,
+            initial_state: cpu.save_state(),
+            cpu,
+        })
+    }
+
+    /// Starts the rom over as it was when loaded, with RND seeded with `seed` or a random seed
+    pub fn reset(&mut self, seed: Option<u64>) {
+        self.cpu.load_state(&self.initial_state).expect("the initial state is a valid state");
+        self.cpu.revive();
+        self.cpu.reseed(seed.unwrap_or_else(rand::random));
+        self.beeper = Beeper::new(&self.audio);
+    }
+
+    /// Runs a frame's worth of instructions with `inputs` held, ticking the timers once
+    pub fn run_frame(&mut self, inputs: &Inputs) -> FrameOutput {
+        self.cpu.set_keys(inputs.keys);
+        let tone = self.cpu.run_frame();
+        let mut samples = Vec::with_capacity(SAMPLES_PER_FRAME);
+        self.beeper.render_frame(tone, &mut samples);
+        FrameOutput {
+            audio_active: tone != Tone::Silent,
+            samples,
+            screen_changed: self.cpu.take_screen_changed(),
+            running: self.cpu.is_running(),
+        }
+    }
+
+    /// `WIDTH` by `HEIGHT` pixels a row at a time, one byte each, 0 for off
+    pub fn framebuffer(&self) -> &[u8] {
+        self.cpu.get_screen()
+    }
+
+    /// The 4K of memory the rom runs in, the rom itself starting at 0x200
+    pub fn memory(&self) -> &[u8] {
+        self.cpu.get_memory()
+    }
+
+    /// Memory to poke at, e.g. for cheats. Changes take effect from the next instruction.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        self.cpu.get_memory_mut()
+    }
+
+    /// Whether the sound timer is running, i.e. the beeper should be on
+    pub fn audio_active(&self) -> bool {
+        self.cpu.sound_timer > 0
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.cpu.is_running()
+    }
+
+    /// Why the rom stopped if it ran something that can't be run, e.g. an unknown opcode.
+    /// `reset` clears it.
+    pub fn error(&self) -> Option<&str> {
+        self.cpu.get_error()
+    }
+
+    /// Everything needed to carry on from this point, including the screen. The same states
+    /// the `crust8cean` binary saves to its slots.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.cpu.save_state()
+    }
+
+    /// Restores a state from `save_state`, leaving the machine as it was if the state is invalid
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        self.cpu.load_state(state)
+    }
+}
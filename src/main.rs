@@ -1,37 +1,126 @@
-extern crate rand;
-extern crate minifb;
-extern crate ears;
-extern crate config;
-
-mod chip8;
-mod tests;
-mod modules;
-
-use chip8::cpu::Cpu;
-use std::{env, thread};
-use std::io::Read;
-use std::io::Result;
-use std::fs::File;
-use std::path::Path;
-use std::time::Duration;
-use crate::modules::config::Config;
+extern crate crust8cean;
+extern crate clap;
+
+mod cli;
+
+use crust8cean::chip8::cpu::{Cpu, FRAMES_PER_SECOND, MAX_PROGRAM_SIZE, PROGRAM_COUNTER_START_ADDR};
+use crust8cean::chip8::{analysis, disasm};
+use crust8cean::modules::config::Config;
+use crust8cean::modules::database::{self, Database, RomInfo};
+use crust8cean::modules::{menu, patch, rom};
+use clap::ArgMatches;
+use std::{process, thread};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 fn main() {
-    let rom_path = env::args().skip(1).next().expect("Usage: ./crust8cean <path-to-rom>");
-    let rom = read_rom(&mut File::open(&Path::new(&rom_path)).unwrap())
-        .expect("rom not found");
+    let matches = cli::build_app().get_matches();
+    if let Err(e) = run(&matches) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), String> {
+    let (command, args) = match matches.subcommand() {
+        (command, Some(args)) => (command, args),
+        _ => unreachable!("clap requires a subcommand"),
+    };
+
+    if command == "config" {
+        return match args.subcommand() {
+            ("dump", Some(dump_args)) => {
+                let rom = match dump_args.value_of("ROM") {
+                    Some(path) => Some(rom::read(path)?),
+                    None => None,
+                };
+                let rom_info = match &rom {
+                    Some(rom) => Database::load()?.lookup(&rom.data)?,
+                    None => None,
+                };
+                let config = cli::load_config(dump_args, rom.as_ref().map(|rom| rom.name.as_str()),
+                                              rom_info.as_ref())?;
+                print!("{}", config.dump()?);
+                Ok(())
+            },
+            _ => unreachable!("clap requires a config subcommand"),
+        };
+    }
+
+    if command == "list" {
+        return list_archive(args.value_of("ARCHIVE").unwrap());
+    }
 
-    let config = Config::new("config");
+    let rom_path = args.value_of("ROM").unwrap();
+    let rom::Rom { name: rom_name, data: rom } = rom::read(rom_path)?;
+    let rom_info = Database::load()?.lookup(&rom)?;
+    let rom = patch_rom(rom, rom_path, args.value_of("patch"))?;
+    let load_config = || cli::load_config(args, Some(&rom_name), rom_info.as_ref());
+    match command {
+        "run" => {
+            let config = load_config()?;
+            if let Some(info) = &rom_info {
+                print_rom_info(info, &config);
+            }
+            run_rom(rom_path, &rom, config, args)
+        },
+        "disasm" => {
+            for line in disasm::disassemble(&rom, PROGRAM_COUNTER_START_ADDR) {
+                println!("{}", line);
+            }
+            Ok(())
+        },
+        "trace" => {
+            let cycles = cli::parse_arg("cycles", args.value_of("cycles").unwrap())?;
+            trace_rom(&rom, load_config()?, cycles, args.is_present("screenshot"), args.value_of("wav"),
+                      args.value_of("script"))
+        },
+        "bench" => {
+            let cycles = cli::parse_arg("cycles", args.value_of("cycles").unwrap())?;
+            bench_rom(&rom, load_config()?, cycles)
+        },
+        "info" => {
+            print_info(rom_path, &rom, rom_info.as_ref());
+            Ok(())
+        },
+        _ => unreachable!("unknown subcommand {}", command),
+    }
+}
+
+fn run_rom(rom_path: &str, rom: &[u8], config: Config, args: &ArgMatches) -> Result<(), String> {
     println!("read config: {:?}", config);
-    let mut cpu = Cpu::new(&rom, config);
-    println!("crust8cean starting...");
-    thread::sleep(Duration::from_millis(3000));
+    let mut cpu = Cpu::new(rom, config)?;
+    cpu.set_rom_path(rom_path);
+    remember_rom(&mut cpu, rom_path);
+    load_cheats(&mut cpu);
+    load_script(&mut cpu, args.value_of("script"))?;
+    println!("crust8cean starting with seed {}...", cpu.get_seed());
+
+    let frame_duration = Duration::from_secs(1) / FRAMES_PER_SECOND;
+    while cpu.is_running() {
+        let frame_start = Instant::now();
+        cpu.run_frame();
 
-    loop {
-        if !cpu.is_running() {
-            break;
+        // roms opened from the menu get their own database entry and profile, like on the command line
+        if let Some(rom_path) = cpu.take_requested_rom() {
+            match load_rom(&rom_path, args) {
+                Ok((rom, title, config)) => match cpu.load_rom(&rom, config) {
+                    Ok(()) => {
+                        cpu.set_rom_path(&rom_path);
+                        cpu.notify(&format!("Loaded {}", title));
+                        remember_rom(&mut cpu, &rom_path);
+                        load_cheats(&mut cpu);
+                    },
+                    Err(e) => cpu.notify(&format!("Error: {}", e)),
+                },
+                Err(e) => cpu.notify(&format!("Error: {}", e)),
+            }
+        }
+
+        let elapsed = frame_start.elapsed();
+        if elapsed < frame_duration {
+            thread::sleep(frame_duration - elapsed);
         }
-        cpu.run();
     }
 
     println!();
@@ -41,11 +130,183 @@ fn main() {
     println!();
     println!("Total cycles emulated: {}", cpu.get_total_cycles());
     println!("Times screen drawn: {}", cpu.get_times_screen_rendered());
+    rom_error(&cpu)
 }
 
-fn read_rom(r: &mut Read) -> Result<Vec<u8>> {
-    let mut data = Vec::new();
-    r.read_to_end(&mut data)?;
+/// The error that stopped the rom, if it didn't stop by itself or by quitting
+fn rom_error(cpu: &Cpu) -> Result<(), String> {
+    match cpu.get_error() {
+        Some(e) => Err(format!("rom stopped: {}", e)),
+        None => Ok(()),
+    }
+}
+
+/// Reads the rom at `rom_path` and the config it runs with, returning it with its title
+fn load_rom(rom_path: &str, args: &ArgMatches) -> Result<(Vec<u8>, String, Config), String> {
+    let rom::Rom { name, data } = rom::read(rom_path)?;
+    let rom_info = Database::load()?.lookup(&data)?;
+    let data = patch_rom(data, rom_path, None)?;
+    let config = cli::load_config(args, Some(&name), rom_info.as_ref())?;
+    let title = rom_info.map_or(name, |info| info.title);
+    Ok((data, title, config))
+}
+
+/// Applies the `--patch` at `patch`, or the patch named like the rom next to it. Roms are
+/// recognised by the database before they're patched, so patched roms keep their quirks and keys.
+fn patch_rom(rom: Vec<u8>, rom_path: &str, patch: Option<&str>) -> Result<Vec<u8>, String> {
+    let path = match patch.map(PathBuf::from).or_else(|| patch::find_patch(rom_path)) {
+        Some(path) => path,
+        None => return Ok(rom),
+    };
+    let patched = patch::apply_file(&rom, &path)?;
+    Cpu::check_program(&patched)
+        .map_err(|e| format!("can't load '{}' patched with '{}': {}", rom_path, path.display(), e))?;
+    println!("Patched with {}", path.display());
+    Ok(patched)
+}
+
+fn remember_rom(cpu: &mut Cpu, rom_path: &str) {
+    if let Err(e) = menu::remember_rom(rom_path) {
+        cpu.notify(&format!("Warning: can't add {} to the recent roms: {}", rom_path, e));
+    }
+}
+
+fn load_cheats(cpu: &mut Cpu) {
+    match cpu.load_cheats() {
+        Ok(0) => (),
+        Ok(count) => cpu.notify(&format!("Loaded {} cheats", count)),
+        Err(e) => cpu.notify(&format!("Warning: can't load cheats: {}", e)),
+    }
+}
+
+/// Loads the `--script` at `path`, if there is one
+#[cfg(feature = "scripting")]
+fn load_script(cpu: &mut Cpu, path: Option<&str>) -> Result<(), String> {
+    match path {
+        Some(path) => cpu.load_script(Path::new(path)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+fn load_script(_cpu: &mut Cpu, path: Option<&str>) -> Result<(), String> {
+    match path {
+        Some(_) => Err("crust8cean was built without the scripting feature".to_string()),
+        None => Ok(()),
+    }
+}
+
+fn trace_rom(rom: &[u8], config: Config, cycles: u64, screenshot: bool, wav: Option<&str>,
+             script: Option<&str>) -> Result<(), String> {
+    let mut cpu = Cpu::new_headless(rom, config);
+    cpu.load_palettes()?;
+    cpu.set_trace(true);
+    if let Some(path) = wav {
+        cpu.start_wav(Path::new(path))?;
+    }
+    load_script(&mut cpu, script)?;
+    while cpu.is_running() && cpu.get_total_cycles() < cycles {
+        cpu.run();
+        // scripts make assertions by failing, which fails the trace
+        #[cfg(feature = "scripting")]
+        {
+            if let Some(e) = cpu.take_script_error() {
+                return Err(e);
+            }
+        }
+    }
+
+    println!("Stopped after {} cycles", cpu.get_total_cycles());
+    if screenshot {
+        for path in cpu.save_screenshot()? {
+            println!("Saved screenshot to {}", path.display());
+        }
+    }
+    if let Some((path, frames)) = cpu.finish_wav()? {
+        println!("Saved {} frames of audio to {}", frames, path.display());
+    }
+    rom_error(&cpu)
+}
+
+fn bench_rom(rom: &[u8], config: Config, cycles: u64) -> Result<(), String> {
+    let mut cpu = Cpu::new_headless(rom, config);
+    let start = Instant::now();
+    while cpu.is_running() && cpu.get_total_cycles() < cycles {
+        cpu.run();
+    }
+    let elapsed = start.elapsed();
+
+    let cycles = cpu.get_total_cycles();
+    println!("Ran {} cycles in {:.3}s", cycles, elapsed.as_secs_f64());
+    println!("{:.0} instructions per second", cycles as f64 / elapsed.as_secs_f64());
+    rom_error(&cpu)
+}
+
+/// Tells the user which rom the database recognised and what the keys do
+fn print_rom_info(info: &RomInfo, config: &Config) {
+    println!("Recognised {}", info.title);
+    if let Some((id, name)) = &info.platform {
+        println!("Platform: {}", name);
+        if !database::is_supported_platform(id) {
+            println!("warning: {} roms use instructions crust8cean doesn't support yet", name);
+        }
+    }
+    for (action, key) in info.keys.iter() {
+        let binding = config.input.keys.get(*key as usize).map(String::as_str).unwrap_or("?");
+        println!("  {:<8} {} (CHIP-8 key {:X})", action, binding, key);
+    }
+}
+
+fn print_info(rom_path: &str, rom: &[u8], rom_info: Option<&RomInfo>) {
+    let end_addr = PROGRAM_COUNTER_START_ADDR as usize + rom.len();
+    let analysis = analysis::analyse(rom, PROGRAM_COUNTER_START_ADDR);
+
+    println!("Rom: {}", rom_path);
+    println!("Size: {} bytes ({} bytes of memory free)", rom.len(), MAX_PROGRAM_SIZE - rom.len());
+    println!("SHA-1: {}", database::sha1_hex(rom));
+    match rom_info {
+        Some(info) => {
+            println!("Title: {}", info.title);
+            if let Some((_, name)) = &info.platform {
+                println!("Platform: {}", name);
+            }
+        },
+        None => println!("Title: unknown (not in the rom database)"),
+    }
+    println!("Detected platform: {}", analysis.platform);
+    println!("Loaded at: 0x{:03X}-0x{:03X}", PROGRAM_COUNTER_START_ADDR, end_addr.saturating_sub(1));
+    println!("Reachable code: {} of {} bytes ({:.0}%)",
+             analysis.reachable_bytes, rom.len(), 100.0 * analysis.reachable_bytes as f64 / rom.len().max(1) as f64);
+
+    if !analysis.unsupported_opcodes.is_empty() {
+        println!("Unsupported extension opcodes: {}", format_opcodes(&analysis.unsupported_opcodes));
+    }
+    if analysis.invalid_opcodes.is_empty() {
+        println!("Invalid opcodes: none");
+    } else {
+        println!("Invalid opcodes: {}", format_opcodes(&analysis.invalid_opcodes));
+    }
+}
+
+fn format_opcodes(opcodes: &[(u16, u16)]) -> String {
+    opcodes.iter()
+        .map(|(addr, opcode)| format!("{:04X} at 0x{:03X}", opcode, addr))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn list_archive(archive_path: &str) -> Result<(), String> {
+    let database = Database::load()?;
+    let entries = rom::read_archive(archive_path)?;
+    let width = entries.iter().map(|entry| entry.name.len()).max().unwrap_or(0);
+    for entry in entries.iter() {
+        let title = match database.lookup(&entry.data)? {
+            Some(info) => info.title,
+            None => String::new(),
+        };
+        println!("{:<width$}  {:>5} bytes  {}", entry.name, entry.data.len(), title, width = width);
+    }
 
-    return Ok(data);
+    println!("{} roms, run one with `crust8cean run {}:NAME`", entries.len(), archive_path);
+    Ok(())
 }
@@ -1,7 +1,187 @@
-use ears::{Sound, AudioController};
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use crate::chip8::cpu::FRAMES_PER_SECOND;
+use crate::modules::config::AudioConfig;
 
-pub fn play_tone() {
-    let mut sound = Sound::new("tone.wav").unwrap();
-    sound.play();
-    while sound.is_playing() { }
-}
\ No newline at end of file
+pub const SAMPLE_RATE: u32 = 44100;
+pub const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAMES_PER_SECOND) as usize;
+
+// the noise waveform's shift register starts here every time the beeper turns on
+const NOISE_SEED: u16 = 1;
+
+/// bytes in an XO-CHIP audio pattern, played a bit at a time from the highest bit of the first byte
+pub const PATTERN_SIZE: usize = 16;
+/// XO-CHIP's initial pitch, playing patterns at 4000 bits per second
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// The shape of the beep's wave
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    /// a 15 bit shift register clocked at the beep's frequency, like the noise channels of old sound chips
+    Noise,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sine" => Ok(Waveform::Sine),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!("unknown waveform '{}', expected one of square/triangle/sine/noise", s)),
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Waveform::Square => "square",
+            Waveform::Triangle => "triangle",
+            Waveform::Sine => "sine",
+            Waveform::Noise => "noise",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What the beeper plays while the sound timer is running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tone {
+    Silent,
+    /// the CHIP-8 beep, shaped by the `[audio]` config
+    Beep,
+    /// an XO-CHIP 1 bit pattern loaded by F002, looped at the rate set by Fx3A's pitch
+    Pattern {
+        pattern: [u8; PATTERN_SIZE],
+        pitch: u8,
+    },
+}
+
+/// Bits per second a pattern plays at, 4000 at the default pitch and doubling every 48 steps
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+}
+
+/// Mono 16 bit at `SAMPLE_RATE`, what the beeper renders
+pub fn wav_spec() -> hound::WavSpec {
+    hound::WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    }
+}
+
+/// Synthesizes the beeper one frame at a time
+pub struct Beeper {
+    waveform: Waveform,
+    frequency: f32,
+    volume: f32,
+    // how much the envelope rises or falls each sample
+    attack_step: f32,
+    release_step: f32,
+    // what's playing, kept after the sound timer stops so it can be released
+    playing: Tone,
+    // how far through the wave or the pattern, from 0 to 1
+    phase: f32,
+    // envelope level from 0 to 1
+    level: f32,
+    noise: u16,
+}
+
+impl Beeper {
+    pub fn new(config: &AudioConfig) -> Self {
+        Beeper {
+            waveform: config.waveform,
+            frequency: config.frequency,
+            volume: config.volume,
+            attack_step: envelope_step(config.attack_ms),
+            release_step: envelope_step(config.release_ms),
+            playing: Tone::Silent,
+            phase: 0.0,
+            level: 0.0,
+            noise: NOISE_SEED,
+        }
+    }
+
+    /// Appends a frame's worth of samples playing `tone` to `samples`. Tones fade in over the
+    /// attack when the beeper turns on, starting from the beginning of their wave or pattern,
+    /// and fade out over the release once it turns off.
+    pub fn render_frame(&mut self, tone: Tone, samples: &mut Vec<i16>) {
+        let on = tone != Tone::Silent;
+        if on {
+            if self.is_silent() {
+                self.phase = 0.0;
+                self.noise = NOISE_SEED;
+            }
+            self.playing = tone;
+        } else if self.is_silent() {
+            samples.extend(std::iter::repeat_n(0, SAMPLES_PER_FRAME));
+            return;
+        }
+
+        let pattern_bits = (PATTERN_SIZE * 8) as f32;
+        let step = match self.playing {
+            Tone::Pattern { pitch, .. } => pattern_rate(pitch) / pattern_bits / SAMPLE_RATE as f32,
+            _ => self.frequency / SAMPLE_RATE as f32,
+        };
+        for _ in 0..SAMPLES_PER_FRAME {
+            self.level = if on {
+                (self.level + self.attack_step).min(1.0)
+            } else {
+                (self.level - self.release_step).max(0.0)
+            };
+
+            let phase = self.phase;
+            let value = match self.playing {
+                Tone::Pattern { pattern, .. } => {
+                    let bit = (phase * pattern_bits) as usize % (PATTERN_SIZE * 8);
+                    if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 }
+                },
+                _ => match self.waveform {
+                    Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+                    Waveform::Triangle if phase < 0.25 => phase * 4.0,
+                    Waveform::Triangle if phase < 0.75 => 2.0 - phase * 4.0,
+                    Waveform::Triangle => phase * 4.0 - 4.0,
+                    Waveform::Sine => (phase * 2.0 * PI).sin(),
+                    Waveform::Noise => if self.noise & 1 != 0 { 1.0 } else { -1.0 },
+                },
+            };
+            samples.push((value * self.volume * self.level * i16::MAX as f32) as i16);
+
+            let next = phase + step;
+            if self.waveform == Waveform::Noise {
+                for _ in 0..next as u32 {
+                    let bit = (self.noise ^ (self.noise >> 1)) & 1;
+                    self.noise = (self.noise >> 1) | (bit << 14);
+                }
+            }
+            self.phase = next.fract();
+        }
+
+        if self.is_silent() {
+            self.playing = Tone::Silent;
+        }
+    }
+
+    /// Whether the beeper has gone quiet, i.e. it's off and done releasing
+    pub fn is_silent(&self) -> bool {
+        self.level == 0.0
+    }
+}
+
+/// Envelope change per sample to go from silent to full volume in `ms` milliseconds
+fn envelope_step(ms: u16) -> f32 {
+    if ms == 0 {
+        1.0
+    } else {
+        1000.0 / (ms as f32 * SAMPLE_RATE as f32)
+    }
+}
@@ -0,0 +1,75 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::modules::filter::Image;
+
+/// Writes `image` to `path` as an 8 bit RGB png
+pub fn save_png(path: &Path, image: &Image) -> Result<(), String> {
+    let file = File::create(path)
+        .map_err(|e| format!("failed to create '{}': {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut data = Vec::with_capacity(image.pixels.len() * 3);
+    for pixel in image.pixels.iter() {
+        data.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8]);
+    }
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(|e| format!("failed to write '{}': {}", path.display(), e))
+}
+
+/// Packs the screen at one bit per pixel, row by row with the leftmost pixel in the highest
+/// bit, the same layout sprites use in memory
+pub fn pack_1bpp(screen: &[u8]) -> Vec<u8> {
+    screen.chunks(8)
+        .map(|pixels| pixels.iter()
+            .enumerate()
+            .fold(0, |byte, (i, pixel)| if *pixel != 0 { byte | (0x80 >> i) } else { byte }))
+        .collect()
+}
+
+pub fn save_raw(path: &Path, screen: &[u8]) -> Result<(), String> {
+    fs::write(path, pack_1bpp(screen))
+        .map_err(|e| format!("failed to write '{}': {}", path.display(), e))
+}
+
+/// A path in `dir` named after the current time, e.g. `crust8cean-2020-01-31_12-00-00.png`.
+/// A counter is added when a file with that name already exists.
+pub fn timestamped_path(dir: &Path, extension: &str) -> Result<PathBuf, String> {
+    fs::create_dir_all(dir)
+        .map_err(|e| format!("failed to create '{}': {}", dir.display(), e))?;
+
+    let stem = format!("crust8cean-{}", timestamp());
+    let mut path = dir.join(format!("{}.{}", stem, extension));
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("{}-{}.{}", stem, n, extension));
+    }
+
+    Ok(path)
+}
+
+/// The current UTC time as `YYYY-MM-DD_HH-MM-SS`
+fn timestamp() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (days, time) = (seconds / 86400, seconds % 86400);
+
+    // days since 1970-01-01 to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}_{:02}-{:02}-{:02}", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
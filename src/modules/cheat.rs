@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::modules::config;
+
+/// How a search compares each address with the value it had at the previous step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    /// holds exactly this value now
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Comparison {
+    fn matches(self, previous: u8, current: u8) -> bool {
+        match self {
+            Comparison::Equal(value) => current == value,
+            Comparison::Changed => current != previous,
+            Comparison::Unchanged => current == previous,
+            Comparison::Increased => current > previous,
+            Comparison::Decreased => current < previous,
+        }
+    }
+}
+
+/// A search for where a rom keeps something, e.g. its lives. It starts with every address and
+/// is narrowed down by comparing memory with a snapshot taken at the previous step, e.g. after
+/// losing a life only addresses that decreased are kept.
+#[derive(Debug, Clone)]
+pub struct Search {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl Search {
+    pub fn new(memory: &[u8]) -> Self {
+        Search {
+            snapshot: memory.to_vec(),
+            candidates: (0..memory.len() as u16).collect(),
+        }
+    }
+
+    /// Keeps the addresses whose value in `memory` matches `comparison`, then snapshots `memory`
+    /// for the next step
+    pub fn narrow(&mut self, memory: &[u8], comparison: Comparison) {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|address| {
+            let address = *address as usize;
+            comparison.matches(snapshot[address], memory[address])
+        });
+        self.snapshot = memory.to_vec();
+    }
+
+    /// The addresses still matching every step so far
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    /// The value `address` had at the last step
+    pub fn previous(&self, address: u16) -> u8 {
+        self.snapshot[address as usize]
+    }
+}
+
+/// An address frozen to a value every frame
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// what the address holds, for the cheats file and the menu
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Cheat {
+    pub fn new(address: u16, value: u8) -> Self {
+        Cheat {
+            address,
+            value,
+            enabled: true,
+            name: String::new(),
+        }
+    }
+}
+
+/// Writes the value of every enabled cheat to its address
+pub fn apply(cheats: &[Cheat], memory: &mut [u8]) {
+    for cheat in cheats.iter().filter(|cheat| cheat.enabled) {
+        if let Some(byte) = memory.get_mut(cheat.address as usize) {
+            *byte = cheat.value;
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/crust8cean/cheats.json`, the cheats for every rom keyed by its SHA-1
+pub fn cheats_path() -> Option<PathBuf> {
+    config::user_config_dir().map(|dir| dir.join("cheats.json"))
+}
+
+/// The cheats saved for the rom with SHA-1 `rom_hash`, none if there's no cheats file
+pub fn load(rom_hash: &str) -> Result<Vec<Cheat>, String> {
+    match cheats_path() {
+        Some(path) => read_cheats(&path, rom_hash),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Saves the cheats for the rom with SHA-1 `rom_hash`, keeping every other rom's
+pub fn save(rom_hash: &str, cheats: &[Cheat]) -> Result<PathBuf, String> {
+    let path = cheats_path()
+        .ok_or_else(|| "can't find the user config directory to save cheats in".to_string())?;
+    write_cheats(&path, rom_hash, cheats)?;
+    Ok(path)
+}
+
+pub fn read_cheats(path: &Path, rom_hash: &str) -> Result<Vec<Cheat>, String> {
+    Ok(read_file(path)?.remove(rom_hash).unwrap_or_default())
+}
+
+/// Replaces the cheats for `rom_hash` in the cheats file at `path`, dropping the rom if it has none
+pub fn write_cheats(path: &Path, rom_hash: &str, cheats: &[Cheat]) -> Result<(), String> {
+    let mut roms = read_file(path)?;
+    if cheats.is_empty() {
+        roms.remove(rom_hash);
+    } else {
+        roms.insert(rom_hash.to_string(), cheats.to_vec());
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create '{}': {}", dir.display(), e))?;
+    }
+    let json = serde_json::to_string_pretty(&roms)
+        .map_err(|e| format!("failed to format cheats: {}", e))?;
+    fs::write(path, json)
+        .map_err(|e| format!("failed to write '{}': {}", path.display(), e))
+}
+
+fn read_file(path: &Path) -> Result<BTreeMap<String, Vec<Cheat>>, String> {
+    if !path.is_file() {
+        return Ok(BTreeMap::new());
+    }
+    let json = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read '{}': {}", path.display(), e))?;
+    serde_json::from_str(&json)
+        .map_err(|e| format!("failed to parse '{}': {}", path.display(), e))
+}
@@ -1,39 +1,499 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{self, DeserializeOwned, Visitor};
+use crate::chip8::cpu::FRAMES_PER_SECOND;
+use crate::chip8::quirks::Quirks;
+use crate::modules::audio::{self, Waveform};
+use crate::modules::database::RomInfo;
+use crate::modules::display::{Backend, Persistence};
+use crate::modules::record::RecordFormat;
+use crate::modules::terminal::Glyphs;
+use crate::modules::{filter, input, palette};
 
-#[derive(Debug)]
+pub const DEFAULT_CONFIG_NAME: &str = "config";
+pub const ENV_PREFIX: &str = "CRUST8CEAN";
+const SECTIONS: [&str; 6] = ["display", "filters", "capture", "audio", "input", "cpu"];
+/// largest integer window scale, 64x32 pixels at 32x is already 2048x1024
+pub const MAX_SCALE: u8 = 32;
+
+/// The effective configuration, merged from (lowest priority first):
+/// 1. the built-in defaults
+/// 2. the user config, `$XDG_CONFIG_HOME/crust8cean/config.toml`
+/// 3. the project config, `./config.toml` or the file passed with `--config`
+/// 4. the rom database entry matching the rom's SHA-1, see `modules::database`
+/// 5. the rom profile, `$XDG_CONFIG_HOME/crust8cean/profiles/<rom name>.toml`
+/// 6. environment variables, e.g. `CRUST8CEAN_DISPLAY__SCALE=4`
+/// 7. command line flags
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
+    pub display: DisplayConfig,
+    pub filters: FilterConfig,
+    pub capture: CaptureConfig,
+    pub audio: AudioConfig,
+    pub input: InputConfig,
+    pub cpu: CpuConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    /// draw the screen at all
+    pub render: bool,
+    /// draw the screen in a window or the terminal
+    #[serde(serialize_with = "serialize_display", deserialize_with = "deserialize_from_str")]
+    pub backend: Backend,
+    /// characters the terminal backend draws with, half-blocks or braille
+    #[serde(serialize_with = "serialize_display", deserialize_with = "deserialize_from_str")]
+    pub terminal_glyphs: Glyphs,
+    /// initial window size as a multiple of the 64x32 screen, from 1 to `MAX_SCALE`. The screen
+    /// is scaled by the largest whole number that fits when the window is resized.
+    pub scale: u8,
+    /// draw lines between the screen's pixels
+    pub grid: bool,
+    /// how long pixels stay lit after being turned off, to reduce flicker
+    #[serde(serialize_with = "serialize_display", deserialize_with = "deserialize_from_str")]
+    pub persistence: Persistence,
+    /// frames a pixel takes to fade out with the decay persistence
+    pub persistence_frames: u8,
+    /// name of the initial palette, one of the built-in palettes or a theme in
+    /// `$XDG_CONFIG_HOME/crust8cean/palettes`
+    pub palette: String,
+    /// pixel color, overriding the palette's foreground until P switches palettes
+    #[serde(skip_serializing_if = "Option::is_none",
+            serialize_with = "serialize_optional_color", deserialize_with = "deserialize_optional_color")]
+    pub color: Option<u32>,
+    /// overrides the palette's background like `color`
+    #[serde(skip_serializing_if = "Option::is_none",
+            serialize_with = "serialize_optional_color", deserialize_with = "deserialize_optional_color")]
+    pub background: Option<u32>,
+    /// show messages like the palette's name on screen, instead of printing them
+    pub osd: bool,
+    /// show frames and instructions run per second on screen, F10 toggles them
+    pub osd_counters: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// filters to run the screen through in order, see `filter::Filter`
+    pub chain: Vec<String>,
+    /// how much scanlines darken every other line, from 0 to 1
+    pub scanline_strength: f32,
+    /// how far glow spreads in pixels
+    pub glow_radius: u8,
+    /// how bright glow is, from 0 to 1
+    pub glow_strength: f32,
+    /// how much barrel distortion bulges the image, from 0 to 1
+    pub barrel_distortion: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// directory screenshots and recordings are saved to
+    pub dir: String,
+    /// also save screenshots as raw 1 bit per pixel data, 256 bytes in the layout sprites use
+    pub raw_screenshots: bool,
+    /// what F9 records to: gif, png (a numbered sequence) or y4m
+    #[serde(serialize_with = "serialize_display", deserialize_with = "deserialize_from_str")]
+    pub record_format: RecordFormat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    pub enabled: bool,
+    /// shape of the beep: square, triangle, sine or noise
+    #[serde(serialize_with = "serialize_display", deserialize_with = "deserialize_from_str")]
+    pub waveform: Waveform,
+    /// pitch of the beep in Hz
+    pub frequency: f32,
+    /// from 0 to 1
+    pub volume: f32,
+    /// milliseconds the beep takes to fade in, so it doesn't click
+    pub attack_ms: u16,
+    /// milliseconds the beep takes to fade out after the sound timer stops
+    pub release_ms: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    /// keyboard key bound to each CHIP-8 key, indexed by the CHIP-8 key's value
+    pub keys: Vec<String>,
+    /// frames a key stays held after it's pressed in the terminal backend. Terminals don't
+    /// report key releases, so this has to outlast the delay before keys start repeating.
+    pub terminal_hold_frames: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CpuConfig {
+    /// print the register state after every instruction
     pub debug: bool,
-    pub render_screen: bool,
-    pub initial_color: u32,
-    pub play_sound: bool,
+    #[serde(serialize_with = "serialize_display", deserialize_with = "deserialize_from_str")]
+    pub quirks: Quirks,
+    pub instructions_per_second: u32,
+    /// seed for RND, picked at random when absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        DisplayConfig {
+            render: true,
+            backend: Backend::Window,
+            terminal_glyphs: Glyphs::HalfBlocks,
+            scale: 10,
+            grid: false,
+            persistence: Persistence::Off,
+            persistence_frames: 4,
+            palette: palette::DEFAULT_PALETTE.to_string(),
+            color: None,
+            background: None,
+            osd: true,
+            osd_counters: false,
+        }
+    }
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig {
+            chain: Vec::new(),
+            scanline_strength: 0.5,
+            glow_radius: 4,
+            glow_strength: 0.5,
+            barrel_distortion: 0.1,
+        }
+    }
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            dir: ".".to_string(),
+            raw_screenshots: false,
+            record_format: RecordFormat::Gif,
+        }
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            enabled: true,
+            waveform: Waveform::Sine,
+            frequency: 1000.0,
+            volume: 0.5,
+            attack_ms: 5,
+            release_ms: 5,
+        }
+    }
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        InputConfig {
+            keys: input::DEFAULT_KEYS.iter().map(|key| key.to_string()).collect(),
+            terminal_hold_frames: 30,
+        }
+    }
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        CpuConfig {
+            debug: false,
+            quirks: Quirks::default(),
+            instructions_per_second: 500,
+            seed: None,
+        }
+    }
+}
+
+/// Where to read the configuration layers from
+#[derive(Debug, Default)]
+pub struct ConfigSources<'a> {
+    /// project config to read instead of `./config.toml`, it's an error for it to be missing
+    pub project: Option<&'a str>,
+    /// rom whose profile should be applied
+    pub rom: Option<&'a str>,
+    /// what the rom database knows about the rom
+    pub rom_info: Option<&'a RomInfo>,
+    /// `(key, value)` pairs from the command line, e.g. `("display.scale", "4")`
+    pub overrides: Vec<(&'static str, String)>,
 }
 
 impl Config {
-    pub fn new(config_name: &str) -> Self {
+    pub fn load(sources: &ConfigSources) -> Result<Self, String> {
         let mut config = config::Config::default();
-        config.merge(config::File::with_name(config_name)).unwrap();
-        let config = config.try_into::<HashMap<String, String>>()
-                .unwrap();
 
-        let debug = read_value("debug", false, &config)
-            .expect("debug should be one of: true/false");
-        let render_screen = read_value("render_screen", true, &config)
-            .expect("render_screen should be one of: true/false");
-        let initial_color = read_value("initial_color", 0xFFFF_FFFF, &config)
-            .expect("initial_color should be a 32bit number");
-        let play_sound = read_value("play_sound", true, &config)
-            .expect("play_sound should be one of: true/false");
+        if let Some(path) = user_config_path() {
+            merge_file(&mut config, &path, false)?;
+        }
+
+        match sources.project {
+            Some(path) => merge_file(&mut config, Path::new(path), true)?,
+            None => merge_file(&mut config, Path::new(DEFAULT_CONFIG_NAME), false)?,
+        }
 
-        Config {
-            debug,
-            render_screen,
-            initial_color,
-            play_sound
+        if let Some(info) = sources.rom_info {
+            config.merge(config::File::from_str(&info.config_layer(FRAMES_PER_SECOND), config::FileFormat::Toml))
+                .map_err(|e| format!("failed to apply rom database entry for {}: {}", info.title, e))?;
         }
+
+        if let Some(path) = sources.rom.and_then(rom_profile_path) {
+            merge_file(&mut config, &path, false)?;
+        }
+
+        config.merge(config::Environment::with_prefix(ENV_PREFIX).separator("__"))
+            .map_err(|e| format!("failed to read {}_* environment variables: {}", ENV_PREFIX, e))?;
+
+        for (key, value) in sources.overrides.iter() {
+            config.set(key, value.as_str())
+                .map_err(|e| format!("invalid value '{}' for {}: {}", value, key, e))?;
+        }
+
+        // deserialize section by section so errors can point at the section the offending key is in
+        let sections = config.clone().try_into::<HashMap<String, config::Value>>()
+            .map_err(|e| format!("invalid config: {}", e))?;
+        if let Some(unknown) = sections.keys().find(|name| !SECTIONS.contains(&name.as_str())) {
+            return Err(format!("invalid config: unknown section [{}], expected one of [{}]",
+                               unknown, SECTIONS.join("], [")));
+        }
+
+        let config = Config {
+            display: read_section(&config, "display")?,
+            filters: read_section(&config, "filters")?,
+            capture: read_section(&config, "capture")?,
+            audio: read_section(&config, "audio")?,
+            input: read_section(&config, "input")?,
+            cpu: read_section(&config, "cpu")?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.display.scale == 0 || self.display.scale > MAX_SCALE {
+            return Err(format!("invalid config: display.scale should be between 1 and {}, got {}",
+                               MAX_SCALE, self.display.scale));
+        }
+        let colors = [("color", self.display.color), ("background", self.display.background)];
+        for (name, color) in colors.iter() {
+            if let Some(color) = color.filter(|color| *color > 0xFF_FFFF) {
+                return Err(format!("invalid config: display.{} should be a 24 bit RRGGBB color, got {:X}",
+                                   name, color));
+            }
+        }
+        let palettes = palette::load_all()
+            .map_err(|e| format!("invalid config: {}", e))?;
+        if !palettes.iter().any(|palette| palette.name == self.display.palette) {
+            let names: Vec<&str> = palettes.iter().map(|palette| palette.name.as_str()).collect();
+            return Err(format!("invalid config: display.palette '{}' doesn't exist, expected one of {}",
+                               self.display.palette, names.join("/")));
+        }
+        if self.display.persistence_frames == 0 {
+            return Err("invalid config: display.persistence_frames should be greater than 0".to_string());
+        }
+        filter::parse_chain(&self.filters.chain)
+            .map_err(|e| format!("invalid config: filters.chain: {}", e))?;
+        let fractions = [("scanline_strength", self.filters.scanline_strength),
+            ("glow_strength", self.filters.glow_strength), ("barrel_distortion", self.filters.barrel_distortion)];
+        for (name, value) in fractions.iter() {
+            if !(0.0..=1.0).contains(value) {
+                return Err(format!("invalid config: filters.{} should be between 0 and 1, got {}", name, value));
+            }
+        }
+        if self.filters.glow_radius == 0 {
+            return Err("invalid config: filters.glow_radius should be greater than 0".to_string());
+        }
+        self.audio.validate()?;
+        self.cpu.validate()?;
+        if self.input.keys.len() != input::KEYBOARD_SIZE {
+            return Err(format!("invalid config: input.keys should list {} keys, one per CHIP-8 key, got {}",
+                               input::KEYBOARD_SIZE, self.input.keys.len()));
+        }
+        for (i, name) in self.input.keys.iter().enumerate() {
+            if input::key_from_name(name).is_none() {
+                return Err(format!("invalid config: input.keys[{}] (CHIP-8 key {:X}) has unknown key '{}'",
+                                   i, i, name));
+            }
+        }
+        if self.input.terminal_hold_frames == 0 {
+            return Err("invalid config: input.terminal_hold_frames should be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// The effective configuration formatted as toml
+    pub fn dump(&self) -> Result<String, String> {
+        toml::to_string(self)
+            .map_err(|e| format!("failed to format config: {}", e))
+    }
+}
+
+impl AudioConfig {
+    /// Checks the `[audio]` section on its own, for machines that have no use for the rest
+    pub fn validate(&self) -> Result<(), String> {
+        let nyquist = audio::SAMPLE_RATE as f32 / 2.0;
+        if !(self.frequency > 0.0 && self.frequency <= nyquist) {
+            return Err(format!("invalid config: audio.frequency should be between 0 and {} Hz, got {}",
+                               nyquist, self.frequency));
+        }
+        if !(0.0..=1.0).contains(&self.volume) {
+            return Err(format!("invalid config: audio.volume should be between 0 and 1, got {}", self.volume));
+        }
+
+        Ok(())
+    }
+}
+
+impl CpuConfig {
+    /// Checks the `[cpu]` section on its own, quirks included
+    pub fn validate(&self) -> Result<(), String> {
+        if self.instructions_per_second == 0 {
+            return Err("invalid config: cpu.instructions_per_second should be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// `$XDG_CONFIG_HOME/crust8cean`, or the platform equivalent
+pub fn user_config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("crust8cean"))
+}
+
+pub fn user_config_path() -> Option<PathBuf> {
+    user_config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// Sets `key`, e.g. `display.scale`, in the user config, keeping everything else in it. Used
+/// by the menu so settings changed in it stick.
+pub fn save_user_setting(key: &str, value: toml::Value) -> Result<(), String> {
+    let path = user_config_path()
+        .ok_or_else(|| "can't find the user config directory".to_string())?;
+    save_setting(&path, key, value)
+}
+
+/// Sets `key` in the config at `path`, creating the file if needed
+pub fn save_setting(path: &Path, key: &str, value: toml::Value) -> Result<(), String> {
+    let (section, name) = match key.find('.') {
+        Some(dot) => (&key[..dot], &key[dot + 1..]),
+        None => return Err(format!("can't save '{}', expected a key like display.scale", key)),
+    };
+
+    let mut config = if path.exists() {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read config '{}': {}", path.display(), e))?;
+        text.parse::<toml::Value>()
+            .map_err(|e| format!("failed to read config '{}': {}", path.display(), e))?
+    } else {
+        toml::Value::Table(toml::value::Table::new())
+    };
+
+    let table = config.as_table_mut()
+        .ok_or_else(|| format!("failed to read config '{}': not a table", path.display()))?;
+    let section = table.entry(section.to_string())
+        .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+    match section.as_table_mut() {
+        Some(section) => section.insert(name.to_string(), value),
+        None => return Err(format!("failed to update config '{}': {} isn't a section", path.display(), key)),
+    };
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create '{}': {}", dir.display(), e))?;
+    }
+    let text = toml::to_string(&config)
+        .map_err(|e| format!("failed to format config: {}", e))?;
+    fs::write(path, text)
+        .map_err(|e| format!("failed to write config '{}': {}", path.display(), e))
+}
+
+/// Profiles are named after the rom's file name without its extension, e.g. `BRIX.toml`
+pub fn rom_profile_path(rom_path: &str) -> Option<PathBuf> {
+    let name = Path::new(rom_path).file_stem()?;
+    user_config_dir().map(|dir| dir.join("profiles").join(name).with_extension("toml"))
+}
+
+fn read_section<T>(config: &config::Config, name: &str) -> Result<T, String>
+    where T: DeserializeOwned + Default {
+    match config.get::<T>(name) {
+        Ok(section) => Ok(section),
+        Err(config::ConfigError::NotFound(_)) => Ok(T::default()),
+        Err(e) => Err(format!("invalid config in [{}]: {}", name, e)),
     }
 }
 
-fn read_value<T: std::str::FromStr>(name: &str, default: T, config: &HashMap<String, String>) -> Result<T, T::Err> {
-    return config.get(name)
-        .map_or(Ok(default), |v| v.parse::<T>());
-}
\ No newline at end of file
+fn merge_file(config: &mut config::Config, path: &Path, required: bool) -> Result<(), String> {
+    let name = path.to_string_lossy();
+    config.merge(config::File::with_name(&name).required(required))
+        .map_err(|e| format!("failed to read config '{}': {}", name, e))?;
+    Ok(())
+}
+
+fn serialize_optional_color<S: Serializer>(color: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error> {
+    match color {
+        Some(color) => serializer.serialize_str(&format!("{:06X}", color)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_optional_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    deserialize_color(deserializer).map(Some)
+}
+
+/// Colors can be given as integers (`0xFF8000`) or hex strings (`"FF8000"`, `"#FF8000"`)
+pub fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    struct ColorVisitor;
+
+    impl<'de> Visitor<'de> for ColorVisitor {
+        type Value = u32;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a color as an integer or a hex string like \"FF8000\"")
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<u32, E> {
+            if value < 0 || value > u32::MAX as i64 {
+                return Err(E::custom(format!("color {} out of range", value)));
+            }
+            Ok(value as u32)
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<u32, E> {
+            self.visit_i64(value as i64)
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<u32, E> {
+            parse_color(value).map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_any(ColorVisitor)
+}
+
+pub fn parse_color(value: &str) -> Result<u32, String> {
+    let hex = value.trim_start_matches('#').trim_start_matches("0x");
+    u32::from_str_radix(hex, 16)
+        .map_err(|_| format!("invalid color '{}', expected a hex color like FF8000", value))
+}
+
+fn serialize_display<T: fmt::Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&value.to_string())
+}
+
+fn deserialize_from_str<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where T: std::str::FromStr, T::Err: fmt::Display, D: Deserializer<'de> {
+    let value = String::deserialize(deserializer)?;
+    value.parse::<T>().map_err(de::Error::custom)
+}
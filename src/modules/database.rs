@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use serde::Deserialize;
+use serde_json::Value;
+use crate::chip8::quirks::Quirks;
+use crate::modules::config;
+
+// a subset of the community chip-8-database (https://github.com/chip-8/chip-8-database) covering
+// the roms in roms/, the full programs.json can be dropped in as the overrides file
+const PROGRAMS_JSON: &str = include_str!("../../database/programs.json");
+const PLATFORMS_JSON: &str = include_str!("../../database/platforms.json");
+const OVERRIDES_FILE_NAME: &str = "database.json";
+/// platforms whose roms only use the original CHIP-8 instruction set
+const SUPPORTED_PLATFORMS: [&str; 4] = ["originalChip8", "hybridVIP", "modernChip8", "chip48"];
+
+/// Everything the database knows about a rom, keyed by the SHA-1 of its contents
+#[derive(Debug, Clone)]
+pub struct RomInfo {
+    pub title: String,
+    /// id and name of the platform the rom was written for, e.g. `("originalChip8", "Cosmac VIP")`
+    pub platform: Option<(String, String)>,
+    pub quirks: Option<Quirks>,
+    pub instructions_per_frame: Option<u32>,
+    pub foreground: Option<u32>,
+    pub background: Option<u32>,
+    /// what each CHIP-8 key does, e.g. `left -> 4`
+    pub keys: BTreeMap<String, u8>,
+}
+
+pub struct Database {
+    // sha1 -> (program title, rom entry)
+    roms: HashMap<String, (String, Value)>,
+    platforms: Vec<Platform>,
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    roms: HashMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RomEntry {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<u32>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, PlatformQuirks>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    id: String,
+    name: String,
+    default_tickrate: Option<u32>,
+    #[serde(default)]
+    quirks: PlatformQuirks,
+}
+
+// vblank isn't emulated, memoryIncrementByX is treated like the regular increment
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct PlatformQuirks {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    logic: Option<bool>,
+}
+
+impl PlatformQuirks {
+    fn apply(&self, quirks: &mut Quirks) {
+        if let Some(shift) = self.shift {
+            quirks.shift = shift;
+        }
+        if let Some(true) = self.memory_increment_by_x {
+            quirks.load_store = false;
+        }
+        if let Some(unchanged) = self.memory_leave_i_unchanged {
+            quirks.load_store = unchanged;
+        }
+        if let Some(wrap) = self.wrap {
+            quirks.clipping = !wrap;
+        }
+        if let Some(jump) = self.jump {
+            quirks.jump = jump;
+        }
+        if let Some(logic) = self.logic {
+            quirks.vf_reset = logic;
+        }
+    }
+}
+
+impl Database {
+    /// Loads the bundled database, with the user's overrides file (if any) merged on top
+    pub fn load() -> Result<Self, String> {
+        let mut database = Database::from_json(PROGRAMS_JSON, PLATFORMS_JSON)
+            .map_err(|e| format!("bundled rom database is invalid: {}", e))?;
+
+        if let Some(path) = overrides_path() {
+            if path.is_file() {
+                let json = fs::read_to_string(&path)
+                    .map_err(|e| format!("failed to read '{}': {}", path.display(), e))?;
+                database.merge_programs(&json)
+                    .map_err(|e| format!("invalid rom database '{}': {}", path.display(), e))?;
+            }
+        }
+
+        Ok(database)
+    }
+
+    pub fn from_json(programs: &str, platforms: &str) -> Result<Self, String> {
+        let platforms = serde_json::from_str(platforms)
+            .map_err(|e| format!("platforms: {}", e))?;
+        let mut database = Database {
+            roms: HashMap::new(),
+            platforms,
+        };
+        database.merge_programs(programs)?;
+        Ok(database)
+    }
+
+    /// Adds the roms in a programs.json style list. Fields of roms that are already known are
+    /// replaced one by one, so overrides only need to list what they change.
+    pub fn merge_programs(&mut self, programs: &str) -> Result<(), String> {
+        let programs: Vec<Program> = serde_json::from_str(programs)
+            .map_err(|e| format!("programs: {}", e))?;
+        for program in programs {
+            for (sha1, rom) in program.roms {
+                let sha1 = sha1.to_lowercase();
+                let merged = match self.roms.remove(&sha1) {
+                    Some((_, mut existing)) => {
+                        merge_json(&mut existing, rom);
+                        existing
+                    },
+                    None => rom,
+                };
+                self.roms.insert(sha1, (program.title.clone(), merged));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Result<Option<RomInfo>, String> {
+        self.lookup_hash(&sha1_hex(rom))
+    }
+
+    pub fn lookup_hash(&self, sha1: &str) -> Result<Option<RomInfo>, String> {
+        let (title, entry) = match self.roms.get(sha1) {
+            Some(rom) => rom,
+            None => return Ok(None),
+        };
+        let entry: RomEntry = serde_json::from_value(entry.clone())
+            .map_err(|e| format!("invalid rom database entry for {} ({}): {}", title, sha1, e))?;
+
+        let platform = entry.platforms.first()
+            .and_then(|id| self.platforms.iter().find(|platform| platform.id == *id));
+        let quirks = platform.map(|platform| {
+            let mut quirks = Quirks::default();
+            platform.quirks.apply(&mut quirks);
+            if let Some(overrides) = entry.quirky_platforms.get(&platform.id) {
+                overrides.apply(&mut quirks);
+            }
+            quirks
+        });
+
+        let pixels = entry.colors.map(|colors| colors.pixels).unwrap_or_default();
+        let color = |i: usize| pixels.get(i).and_then(|color| config::parse_color(color).ok());
+
+        Ok(Some(RomInfo {
+            title: title.clone(),
+            platform: platform.map(|platform| (platform.id.clone(), platform.name.clone())),
+            quirks,
+            instructions_per_frame: entry.tickrate.or_else(|| platform.and_then(|p| p.default_tickrate)),
+            background: color(0),
+            foreground: color(1),
+            keys: entry.keys,
+        }))
+    }
+}
+
+impl RomInfo {
+    /// The settings from the database as a config layer
+    pub fn config_layer(&self, frames_per_second: u32) -> String {
+        let mut display = toml::value::Table::new();
+        if let Some(foreground) = self.foreground {
+            display.insert("color".to_string(), toml::Value::Integer(foreground as i64));
+        }
+        if let Some(background) = self.background {
+            display.insert("background".to_string(), toml::Value::Integer(background as i64));
+        }
+
+        let mut cpu = toml::value::Table::new();
+        if let Some(quirks) = self.quirks {
+            cpu.insert("quirks".to_string(), toml::Value::String(quirks.to_string()));
+        }
+        if let Some(per_frame) = self.instructions_per_frame {
+            let per_second = (per_frame * frames_per_second) as i64;
+            cpu.insert("instructions_per_second".to_string(), toml::Value::Integer(per_second));
+        }
+
+        let mut layer = toml::value::Table::new();
+        layer.insert("display".to_string(), toml::Value::Table(display));
+        layer.insert("cpu".to_string(), toml::Value::Table(cpu));
+        toml::Value::Table(layer).to_string()
+    }
+}
+
+pub fn is_supported_platform(id: &str) -> bool {
+    SUPPORTED_PLATFORMS.contains(&id)
+}
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    sha1::Sha1::from(data).digest().to_string()
+}
+
+/// `$XDG_CONFIG_HOME/crust8cean/database.json`
+pub fn overrides_path() -> Option<PathBuf> {
+    config::user_config_dir().map(|dir| dir.join(OVERRIDES_FILE_NAME))
+}
+
+fn merge_json(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    },
+                }
+            }
+        },
+        (base, overrides) => *base = overrides,
+    }
+}
@@ -0,0 +1,51 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// A command typed into the terminal while running with `--debug`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// save the screen like F12 does
+    Screenshot,
+    /// save the screen as raw 1 bit per pixel data only
+    Raw,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        match line.trim() {
+            "screenshot" | "s" => Ok(Command::Screenshot),
+            "raw" | "r" => Ok(Command::Raw),
+            command => Err(format!("unknown debugger command '{}', expected screenshot or raw", command)),
+        }
+    }
+}
+
+/// Reads debugger commands from stdin a line at a time. Lines are read in a thread of their own,
+/// so the emulator never waits for them.
+pub struct Console {
+    lines: Receiver<String>,
+}
+
+impl Console {
+    pub fn start() -> Self {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            // until stdin ends, or the console is gone
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Console { lines }
+    }
+
+    /// The commands typed since the last call, blank lines skipped
+    pub fn commands(&self) -> Vec<Result<Command, String>> {
+        self.lines.try_iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Command::parse(&line))
+            .collect()
+    }
+}
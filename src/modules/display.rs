@@ -1,9 +1,21 @@
+use std::fmt;
+use std::str::FromStr;
+#[cfg(feature = "desktop")]
 use minifb::Window;
+use crate::modules::config::{DisplayConfig, FilterConfig};
+use crate::modules::filter::{FilterPipeline, Image};
+use crate::modules::osd::{self, Anchor};
+use crate::modules::palette::{self, Palette};
 
-const SCREEN_WIDTH: u16 = 64;
-const SCREEN_HEIGHT: u16 = 32;
+pub const SCREEN_WIDTH: u16 = 64;
+pub const SCREEN_HEIGHT: u16 = 32;
 pub const SCREEN_SIZE: usize = SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize;
 
+// drawn between pixels when grid lines are enabled
+const GRID_COLOR: u32 = 0x20_2020;
+// letterbox bars
+const BORDER_COLOR: u32 = 0x00_0000;
+
 // sprites
 pub const BYTES_PER_CHARACTER: u8 = 5;
 pub const NUM_FONT_CHARACTERS: u8 = 16;
@@ -107,51 +119,150 @@ pub const FONT_SPRITES: [u8; FONT_ARRAY_SIZE] = [
     0b10000000,
     0b10000000];
 
-pub const COLORS: [u32; 14] = [
-                            // white
-                            0xFFFFFF,
-                            // red
-                            0xFF0000,
-                            // orange
-                            0xFF8000,
-                            // yellow
-                            0xFFFF00,
-                            // light green
-                            0x80FF00,
-                            // green
-                            0x00FF00,
-                            // dark green
-                            0x00FF80,
-                            // teal
-                            0x00FFFF,
-                            // blue
-                            0x0080FF,
-                            // dark blue
-                            0x0000FF,
-                            // purple
-                            0x7F00FF,
-                            // pink
-                            0xFF00FF,
-                            // magenta
-                            0xFF007F,
-                            // gray
-                            0x808080
-                        ];
+/// A rectangle of screen pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    /// The smallest rectangle covering both
+    pub fn union(&self, other: &Rect) -> Rect {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+/// Where the screen is drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// a window, scaled and filtered
+    Window,
+    /// the terminal the emulator was started from, see `terminal::Terminal`
+    Terminal,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "window" => Ok(Backend::Window),
+            "terminal" => Ok(Backend::Terminal),
+            _ => Err(format!("unknown backend '{}', expected window or terminal", s)),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Backend::Window => "window",
+            Backend::Terminal => "terminal",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How long lit pixels stay on screen after the interpreter turns them off. Games erase and redraw
+/// sprites with XOR every frame, which flickers badly without some persistence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Persistence {
+    /// pixels go dark as soon as they're turned off
+    Off,
+    /// pixels fade out over `DisplayConfig::persistence_frames` frames like phosphor
+    Decay,
+    /// pixels lit in either of the last two frames are drawn
+    Or,
+}
+
+impl FromStr for Persistence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Persistence::Off),
+            "decay" => Ok(Persistence::Decay),
+            "or" => Ok(Persistence::Or),
+            _ => Err(format!("unknown persistence mode '{}', expected one of off/decay/or", s)),
+        }
+    }
+}
+
+impl fmt::Display for Persistence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Persistence::Off => "off",
+            Persistence::Decay => "decay",
+            Persistence::Or => "or",
+        };
+        write!(f, "{}", name)
+    }
+}
 
 pub struct MiniFbDisplay {
-    color: u32,
+    foreground: u32,
+    background: u32,
+    palettes: Vec<Palette>,
+    palette: usize,
     redraw: bool,
     screen: [u8; SCREEN_SIZE],
     times_rendered: u64,
+    grid: bool,
+    // size of the window the last frame was drawn for, the scale follows the window when it's resized
+    window_size: (usize, usize),
+    buffer: Vec<u32>,
+    filters: FilterPipeline,
+    persistence: Persistence,
+    // how much a pixel's intensity drops each frame after it's turned off, out of 255
+    decay_step: u8,
+    // per pixel brightness out of 255, for the decay mode
+    intensity: [u8; SCREEN_SIZE],
+    // the screen as of the last frame, for the or mode
+    previous_screen: [u8; SCREEN_SIZE],
+    // pixels are still fading out, so the screen has to be redrawn even if nothing changed
+    fading: bool,
+    // pixels changed since the last render
+    dirty: Option<Rect>,
+    // the osd lines drawn over the last frame
+    overlay: Vec<(Anchor, String)>,
 }
 
 impl MiniFbDisplay {
-    pub fn new(initial_color: u32) -> Self {
+    pub fn new(config: &DisplayConfig, filters: &FilterConfig) -> Self {
+        let scale = config.scale as usize;
+        let frames = config.persistence_frames.max(1) as u16;
+        // the display never reads the user's themes itself, `set_palettes` adds them and checks
+        // that the palette exists. Until then a theme's name starts on the first built-in palette.
+        let palettes = palette::builtin();
+        let index = palettes.iter().rposition(|palette| palette.name == config.palette).unwrap_or(0);
         MiniFbDisplay {
-            color: initial_color,
+            foreground: config.color.unwrap_or(palettes[index].foreground),
+            background: config.background.unwrap_or(palettes[index].background),
+            palettes,
+            palette: index,
             redraw: false,
             screen: [0; SCREEN_SIZE],
             times_rendered: 0,
+            grid: config.grid,
+            window_size: (SCREEN_WIDTH as usize * scale, SCREEN_HEIGHT as usize * scale),
+            buffer: Vec::new(),
+            filters: FilterPipeline::new(filters),
+            persistence: config.persistence,
+            decay_step: 255_u16.div_ceil(frames) as u8,
+            intensity: [0; SCREEN_SIZE],
+            previous_screen: [0; SCREEN_SIZE],
+            fading: false,
+            dirty: None,
+            overlay: Vec::new(),
         }
     }
 
@@ -160,57 +271,287 @@ impl MiniFbDisplay {
     }
 
     pub fn write(&mut self, pos: usize, value: u8) {
-        self.screen[pos] = value;
+        if self.screen[pos] != value {
+            self.screen[pos] = value;
+            self.mark_dirty(pos);
+        }
     }
 
     pub fn set_redraw(&mut self, redraw: bool) {
         self.redraw = redraw;
     }
 
-    pub fn change_color(&mut self) {
-        for (i, color) in COLORS.iter().enumerate() {
-            if self.color == *color {
-                let index = if i + 1 < COLORS.len() {
-                    i + 1
-                } else {
-                    0
-                };
+    /// Switches to the next palette, returning it
+    pub fn next_palette(&mut self) -> &Palette {
+        self.palette = (self.palette + 1) % self.palettes.len();
+        let palette = &self.palettes[self.palette];
+        self.foreground = palette.foreground;
+        self.background = palette.background;
+        self.redraw = true;
+        palette
+    }
 
-                self.color = COLORS[index];
-                break;
-            }
+    /// Switches to the palette named `name`, returning false if there's no such palette
+    pub fn set_palette(&mut self, name: &str) -> bool {
+        match self.palettes.iter().rposition(|palette| palette.name == name) {
+            Some(index) => {
+                self.palette = (index + self.palettes.len() - 1) % self.palettes.len();
+                self.next_palette();
+                true
+            },
+            None => false,
         }
     }
 
+    /// Replaces the palettes P cycles through and switches to the one `config` picks, with its
+    /// colors overridden by `config`'s
+    pub fn set_palettes(&mut self, palettes: Vec<Palette>, config: &DisplayConfig) -> Result<(), String> {
+        let index = palettes.iter().rposition(|palette| palette.name == config.palette)
+            .ok_or_else(|| format!("palette '{}' doesn't exist", config.palette))?;
+        self.foreground = config.color.unwrap_or(palettes[index].foreground);
+        self.background = config.background.unwrap_or(palettes[index].background);
+        self.palettes = palettes;
+        self.palette = index;
+        self.redraw = true;
+        Ok(())
+    }
+
+    pub fn palettes(&self) -> &[Palette] {
+        &self.palettes
+    }
+
+    /// Names of the palettes P cycles through and the index of the current one
+    pub fn get_palettes(&self) -> (Vec<String>, usize) {
+        (self.palettes.iter().map(|palette| palette.name.clone()).collect(), self.palette)
+    }
+
     pub fn clear(&mut self) {
-        for pixel in self.screen.iter_mut() {
-            if *pixel != 0 {
-                self.redraw = true;
-                *pixel = 0;
-            }
+        for pos in 0..self.screen.len() {
+            self.write(pos, 0);
         }
     }
 
-    pub fn render(&mut self, window: &mut Window) {
-        if !self.redraw {
-            return;
+    /// The rectangle of pixels changed since the last call, or since the screen was last rendered
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        self.dirty.take()
+    }
+
+    fn mark_dirty(&mut self, pos: usize) {
+        let pixel = Rect {
+            x: pos % SCREEN_WIDTH as usize,
+            y: pos / SCREEN_WIDTH as usize,
+            width: 1,
+            height: 1,
+        };
+        self.dirty = Some(match &self.dirty {
+            Some(dirty) => dirty.union(&pixel),
+            None => pixel,
+        });
+    }
+
+    /// Draws the screen in `window` with the `overlay` from the `Osd` on top
+    #[cfg(feature = "desktop")]
+    pub fn render(&mut self, window: &mut Window, overlay: &[(Anchor, String)]) {
+        let (width, height) = window.get_size();
+        if let Some(buffer) = self.render_to_buffer(width, height, overlay) {
+            // unwrap, we want to know if this fails
+            window.update_with_buffer(buffer).unwrap();
+        } else {
+            // minifb only reads input and window events while updating, so the window updates
+            // every frame even when the screen hasn't changed
+            window.update();
         }
+    }
 
-        let mut buf: [u32; SCREEN_SIZE] = [0; SCREEN_SIZE];
-        for (i, pixel) in self.screen.iter_mut().enumerate() {
-            if *pixel != 0 {
-                buf[i] = self.color;
-            }
+    /// Draws the screen into a `width`x`height` buffer the way `render` draws it in a window.
+    /// Returns the buffer, or nothing if it hasn't changed since the last call.
+    pub fn render_to_buffer(&mut self, width: usize, height: usize, overlay: &[(Anchor, String)]) -> Option<&[u32]> {
+        if (width, height) != self.window_size {
+            self.window_size = (width, height);
+            self.redraw = true;
+        }
+        if overlay != &self.overlay[..] {
+            self.overlay = overlay.to_vec();
+            self.redraw = true;
+        }
+        let dirty = self.take_dirty();
+        if !self.redraw && !self.fading && dirty.is_none() {
+            return None;
+        }
+
+        match dirty {
+            // without persistence, filters or an overlay a pixel only affects its own block of the window
+            Some(dirty) if !self.redraw && self.persistence == Persistence::Off && self.filters.is_empty()
+                && self.overlay.is_empty() && self.buffer.len() == width * height => self.redraw_region(dirty),
+            _ => {
+                let frame = self.next_frame();
+                let image = self.filters.process(frame, width, height, self.grid);
+                letterbox(&image.pixels, image.width, image.height, &mut self.buffer, width, height);
+                osd::draw(&self.overlay, &mut self.buffer, width, height);
+            },
         }
 
         self.redraw = false;
         self.times_rendered += 1;
-        // unwrap, we want to know if this fails
-        window.update_with_buffer(&buf).unwrap();
+        Some(&self.buffer)
+    }
+
+    /// Redraws just the pixels in `region` into the buffer, drawn the same as a full render
+    /// without persistence or filters
+    fn redraw_region(&mut self, region: Rect) {
+        let (width, height) = self.window_size;
+        let scale = fit_scale(width, height);
+        let (image_width, image_height) = (SCREEN_WIDTH as usize * scale, SCREEN_HEIGHT as usize * scale);
+        let (left, top) = ((width.max(image_width) - image_width) / 2, (height.max(image_height) - image_height) / 2);
+        let grid = self.grid && scale >= 3;
+
+        for y in region.y * scale..(region.y + region.height) * scale {
+            for x in region.x * scale..(region.x + region.width) * scale {
+                // windows smaller than the screen crop it
+                if left + x >= width || top + y >= height {
+                    continue;
+                }
+                let color = if grid && (x % scale == scale - 1 || y % scale == scale - 1) {
+                    GRID_COLOR
+                } else if self.screen[(y / scale) * SCREEN_WIDTH as usize + x / scale] != 0 {
+                    self.foreground
+                } else {
+                    self.background
+                };
+                self.buffer[(top + y) * width + left + x] = color;
+            }
+        }
+    }
+
+    /// The foreground and background colors the screen is drawn in
+    pub fn get_colors(&self) -> (u32, u32) {
+        (self.foreground, self.background)
     }
 
     pub fn get_times_rendered(&self) -> u64 {
         self.times_rendered
     }
 
-}
\ No newline at end of file
+    /// one byte per pixel, 0 for off
+    pub fn get_screen(&self) -> &[u8] {
+        &self.screen
+    }
+
+    /// The screen exactly as the interpreter sees it in the current palette, scaled up by
+    /// `scale` without persistence or filters
+    pub fn screenshot(&self, scale: usize) -> Image {
+        let pixels: Vec<u32> = self.screen.iter()
+            .map(|pixel| if *pixel != 0 { self.foreground } else { self.background })
+            .collect();
+        Image {
+            width: SCREEN_WIDTH as usize * scale,
+            height: SCREEN_HEIGHT as usize * scale,
+            pixels: upscale(&pixels, SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize, scale),
+        }
+    }
+
+    /// The screen's colors for this frame, with persistence applied. Advances persistence by a
+    /// frame, so call it once per frame.
+    pub fn next_frame(&mut self) -> Image {
+        let pixels = match self.persistence {
+            Persistence::Off => self.screen.iter()
+                .map(|pixel| if *pixel != 0 { self.foreground } else { self.background })
+                .collect(),
+            Persistence::Decay => {
+                let (foreground, background, decay_step) = (self.foreground, self.background, self.decay_step);
+                let mut fading = false;
+                let pixels = self.screen.iter().zip(self.intensity.iter_mut())
+                    .map(|(pixel, intensity)| {
+                        *intensity = if *pixel != 0 {
+                            255
+                        } else {
+                            intensity.saturating_sub(decay_step)
+                        };
+                        fading |= *intensity != 0 && *pixel == 0;
+                        fade(background, foreground, *intensity)
+                    })
+                    .collect();
+                self.fading = fading;
+                pixels
+            },
+            Persistence::Or => {
+                let pixels = self.screen.iter().zip(self.previous_screen.iter())
+                    .map(|(pixel, previous)| if *pixel != 0 || *previous != 0 { self.foreground } else { self.background })
+                    .collect();
+                // the previous frame has to drop off the screen once the screen stops changing
+                self.fading = self.screen[..] != self.previous_screen[..];
+                self.previous_screen = self.screen;
+                pixels
+            },
+        };
+
+        Image {
+            width: SCREEN_WIDTH as usize,
+            height: SCREEN_HEIGHT as usize,
+            pixels,
+        }
+    }
+
+}
+
+/// Blends from `background` at intensity 0 to `foreground` at 255
+fn fade(background: u32, foreground: u32, intensity: u8) -> u32 {
+    let intensity = intensity as i32;
+    let channel = |shift: u32| -> u32 {
+        let from = ((background >> shift) & 0xFF) as i32;
+        let to = ((foreground >> shift) & 0xFF) as i32;
+        (from + (to - from) * intensity / 255) as u32
+    };
+    (channel(16) << 16) | (channel(8) << 8) | channel(0)
+}
+
+/// The largest integer scale at which the screen fits in a `width`x`height` window, at least 1
+pub fn fit_scale(width: usize, height: usize) -> usize {
+    (width / SCREEN_WIDTH as usize).min(height / SCREEN_HEIGHT as usize).max(1)
+}
+
+/// Scales `image` up by an integer factor, each pixel becoming a `scale`x`scale` block
+pub fn upscale(image: &[u32], width: usize, height: usize, scale: usize) -> Vec<u32> {
+    let scaled_width = width * scale;
+    let mut scaled = vec![0; scaled_width * height * scale];
+    for (y, row) in scaled.chunks_mut(scaled_width).enumerate() {
+        let source_row = &image[(y / scale) * width..(y / scale + 1) * width];
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = source_row[x / scale];
+        }
+    }
+
+    scaled
+}
+
+/// Draws grid lines over the last row and column of every `cell`x`cell` block, when cells are
+/// big enough to spare them
+pub fn draw_grid(image: &mut [u32], width: usize, cell: usize) {
+    if cell < 3 {
+        return;
+    }
+
+    for (y, row) in image.chunks_mut(width).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            if y % cell == cell - 1 || x % cell == cell - 1 {
+                *pixel = GRID_COLOR;
+            }
+        }
+    }
+}
+
+/// Centers `image` in a `width`x`height` buffer, filling the rest with black bars. Images
+/// bigger than the buffer are cropped.
+pub fn letterbox(image: &[u32], image_width: usize, image_height: usize,
+                 buffer: &mut Vec<u32>, width: usize, height: usize) {
+    buffer.clear();
+    buffer.resize(width * height, BORDER_COLOR);
+
+    let (copy_width, copy_height) = (image_width.min(width), image_height.min(height));
+    let (left, top) = ((width - copy_width) / 2, (height - copy_height) / 2);
+    for y in 0..copy_height {
+        let source = &image[y * image_width..y * image_width + copy_width];
+        let start = (top + y) * width + left;
+        buffer[start..start + copy_width].copy_from_slice(source);
+    }
+}
@@ -0,0 +1,387 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::modules::config::FilterConfig;
+use crate::modules::display;
+
+/// Post processing applied to the screen before it's drawn, in the spirit of VBA's filters.
+/// Upscalers work on the screen's pixels and run first, the other filters run on the window
+/// sized image after the screen has been scaled up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Scale2x, a.k.a. EPX: doubles the resolution, rounding off diagonal edges
+    Scale2x,
+    /// doubles the resolution, blending edges using a reduced set of the hq2x interpolation rules
+    Hq2x,
+    /// darkens every other line like the gaps between a CRT's scanlines
+    Scanlines,
+    /// adds a blurred copy of the image on top of itself so lit pixels bleed into their surroundings
+    Glow,
+    /// bulges the image out like the curved glass of a CRT
+    Barrel,
+}
+
+const FILTER_NAMES: [&str; 5] = ["scale2x", "hq2x", "scanlines", "glow", "barrel"];
+
+impl Filter {
+    /// Upscalers double the resolution of the screen and have to run before it's scaled to the window
+    pub fn is_upscaler(self) -> bool {
+        matches!(self, Filter::Scale2x | Filter::Hq2x)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "scale2x" | "epx" => Ok(Filter::Scale2x),
+            "hq2x" => Ok(Filter::Hq2x),
+            "scanlines" => Ok(Filter::Scanlines),
+            "glow" => Ok(Filter::Glow),
+            "barrel" => Ok(Filter::Barrel),
+            _ => Err(format!("unknown filter '{}', expected one of {}", s, FILTER_NAMES.join("/"))),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Filter::Scale2x => "scale2x",
+            Filter::Hq2x => "hq2x",
+            Filter::Scanlines => "scanlines",
+            Filter::Glow => "glow",
+            Filter::Barrel => "barrel",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Parses a list of filter names, checking upscalers come before every other filter
+pub fn parse_chain(names: &[String]) -> Result<Vec<Filter>, String> {
+    let chain = names.iter()
+        .map(|name| name.parse::<Filter>())
+        .collect::<Result<Vec<Filter>, String>>()?;
+
+    if let Some(i) = chain.iter().position(|filter| !filter.is_upscaler()) {
+        if let Some(upscaler) = chain[i..].iter().find(|filter| filter.is_upscaler()) {
+            return Err(format!("{} has to come before {}, upscalers run before the screen is scaled to the window",
+                               upscaler, chain[i]));
+        }
+    }
+
+    Ok(chain)
+}
+
+/// An image being passed down the pipeline
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+/// Runs a chain of filters, caching what it can between frames
+pub struct FilterPipeline {
+    chain: Vec<Filter>,
+    options: FilterConfig,
+    // source index for every pixel of the last barrel distorted size, u32::MAX for pixels
+    // outside the bulged image
+    barrel_map: Option<(usize, usize, Vec<u32>)>,
+}
+
+impl FilterPipeline {
+    /// `options` must have been validated, see `Config::validate`
+    pub fn new(options: &FilterConfig) -> Self {
+        FilterPipeline {
+            chain: parse_chain(&options.chain).unwrap_or_default(),
+            options: options.clone(),
+            barrel_map: None,
+        }
+    }
+
+    /// No filters to run, the screen is only scaled
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+    }
+
+    /// Runs the screen through the chain, scaling it up to the largest whole number multiple of
+    /// its size that fits in `max_width`x`max_height`
+    pub fn process(&mut self, screen: Image, max_width: usize, max_height: usize, grid: bool) -> Image {
+        let mut image = screen;
+        let pixel_scale = display::fit_scale(max_width, max_height);
+
+        let upscalers = self.chain.iter().take_while(|filter| filter.is_upscaler()).count();
+        for filter in self.chain[..upscalers].iter() {
+            image = match filter {
+                Filter::Scale2x => scale2x(&image),
+                _ => hq2x(&image),
+            };
+        }
+
+        // upscalers can overshoot small windows, in which case the image gets cropped
+        let source_scale = image.width / display::SCREEN_WIDTH as usize;
+        let scale = (pixel_scale / source_scale).max(1);
+        let pixels = display::upscale(&image.pixels, image.width, image.height, scale);
+        image = Image {
+            width: image.width * scale,
+            height: image.height * scale,
+            pixels,
+        };
+        if grid {
+            display::draw_grid(&mut image.pixels, image.width, scale * source_scale);
+        }
+
+        for i in upscalers..self.chain.len() {
+            match self.chain[i] {
+                Filter::Scanlines => scanlines(&mut image, self.options.scanline_strength),
+                Filter::Glow => image = glow(&image, self.options.glow_radius as usize, self.options.glow_strength),
+                Filter::Barrel => image = self.barrel(&image),
+                Filter::Scale2x | Filter::Hq2x => unreachable!("upscalers run first"),
+            }
+        }
+
+        image
+    }
+
+    fn barrel(&mut self, image: &Image) -> Image {
+        let stale = match &self.barrel_map {
+            Some((width, height, _)) => *width != image.width || *height != image.height,
+            None => true,
+        };
+        if stale {
+            let map = barrel_map(image.width, image.height, self.options.barrel_distortion);
+            self.barrel_map = Some((image.width, image.height, map));
+        }
+
+        let map = &self.barrel_map.as_ref().unwrap().2;
+        Image {
+            width: image.width,
+            height: image.height,
+            pixels: map.iter()
+                .map(|source| if *source == u32::MAX { 0 } else { image.pixels[*source as usize] })
+                .collect(),
+        }
+    }
+}
+
+/// Scale2x: each pixel becomes 2x2, with a corner taking the color of its two neighbours when
+/// they agree with each other and not with the pixels across from them
+pub fn scale2x(image: &Image) -> Image {
+    let (width, height) = (image.width, image.height);
+    let pixel = |x: isize, y: isize| -> u32 {
+        let x = x.max(0).min(width as isize - 1) as usize;
+        let y = y.max(0).min(height as isize - 1) as usize;
+        image.pixels[y * width + x]
+    };
+
+    let mut pixels = vec![0; width * height * 4];
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let p = pixel(x, y);
+            let (a, b, c, d) = (pixel(x, y - 1), pixel(x + 1, y), pixel(x - 1, y), pixel(x, y + 1));
+            let mut out = [p; 4];
+            if c == a && c != d && a != b {
+                out[0] = a;
+            }
+            if a == b && a != c && b != d {
+                out[1] = b;
+            }
+            if d == c && d != b && c != a {
+                out[2] = c;
+            }
+            if b == d && b != a && d != c {
+                out[3] = d;
+            }
+
+            let (x, y) = (x as usize * 2, y as usize * 2);
+            pixels[y * width * 2 + x] = out[0];
+            pixels[y * width * 2 + x + 1] = out[1];
+            pixels[(y + 1) * width * 2 + x] = out[2];
+            pixels[(y + 1) * width * 2 + x + 1] = out[3];
+        }
+    }
+
+    Image {
+        width: width * 2,
+        height: height * 2,
+        pixels,
+    }
+}
+
+/// hq2x: each pixel becomes 2x2, with every corner blended from the pixel and the neighbours
+/// on that side. Neighbours are compared in YUV with hq2x's thresholds, but instead of the full
+/// 256 case lookup table only the cases that matter for 1 bit art are handled: corners on a
+/// diagonal edge take the average of the edge, other corners lean towards distinct diagonals.
+pub fn hq2x(image: &Image) -> Image {
+    let (width, height) = (image.width, image.height);
+    let pixel = |x: isize, y: isize| -> u32 {
+        let x = x.max(0).min(width as isize - 1) as usize;
+        let y = y.max(0).min(height as isize - 1) as usize;
+        image.pixels[y * width + x]
+    };
+
+    let mut pixels = vec![0; width * height * 4];
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let center = pixel(x, y);
+            let (ox, oy) = (x as usize * 2, y as usize * 2);
+            for (i, &(dx, dy)) in [(-1isize, -1isize), (1, -1), (-1, 1), (1, 1)].iter().enumerate() {
+                let vertical = pixel(x, y + dy);
+                let horizontal = pixel(x + dx, y);
+                let diagonal = pixel(x + dx, y + dy);
+
+                let color = if !yuv_differs(vertical, horizontal)
+                    && yuv_differs(center, vertical) && yuv_differs(center, horizontal) {
+                    // on a diagonal edge, round it off
+                    blend(&[(center, 2), (vertical, 1), (horizontal, 1)])
+                } else if yuv_differs(center, diagonal) {
+                    blend(&[(center, 3), (diagonal, 1)])
+                } else {
+                    center
+                };
+
+                let (sx, sy) = (ox + i % 2, oy + i / 2);
+                pixels[sy * width * 2 + sx] = color;
+            }
+        }
+    }
+
+    Image {
+        width: width * 2,
+        height: height * 2,
+        pixels,
+    }
+}
+
+/// Darkens every other line by `strength`, from 0 (no effect) to 1 (black)
+pub fn scanlines(image: &mut Image, strength: f32) {
+    let keep = ((1.0 - strength) * 256.0) as u32;
+    for row in image.pixels.chunks_mut(image.width).skip(1).step_by(2) {
+        for pixel in row.iter_mut() {
+            *pixel = scale_color(*pixel, keep);
+        }
+    }
+}
+
+/// Adds a box blurred copy of the image weighted by `strength`. The blur is separable and uses
+/// running sums, so it costs the same for any radius.
+pub fn glow(image: &Image, radius: usize, strength: f32) -> Image {
+    let (width, height) = (image.width, image.height);
+    let horizontal = box_blur(&image.pixels, width, height, radius, 1, width);
+    let blurred = box_blur(&horizontal, height, width, radius, width, 1);
+
+    let weight = (strength * 256.0) as u32;
+    Image {
+        width,
+        height,
+        pixels: image.pixels.iter().zip(blurred.iter())
+            .map(|(pixel, blur)| add_colors(*pixel, scale_color(*blur, weight)))
+            .collect(),
+    }
+}
+
+/// Blurs `lines` lines of `length` pixels each, `step` apart within a line and `stride` apart
+/// between lines
+fn box_blur(pixels: &[u32], length: usize, lines: usize, radius: usize, step: usize, stride: usize) -> Vec<u32> {
+    let mut blurred = vec![0; pixels.len()];
+    let window = (radius * 2 + 1) as u32;
+    for line in 0..lines {
+        let at = |i: usize| pixels[line * stride + i * step];
+        let mut sums = [0u32; 3];
+        for i in 0..radius.min(length) {
+            add_to_sums(&mut sums, at(i), true);
+        }
+
+        for i in 0..length {
+            if i + radius < length {
+                add_to_sums(&mut sums, at(i + radius), true);
+            }
+            if i > radius {
+                add_to_sums(&mut sums, at(i - radius - 1), false);
+            }
+            blurred[line * stride + i * step] = (sums[0] / window) << 16 | (sums[1] / window) << 8 | (sums[2] / window);
+        }
+    }
+
+    blurred
+}
+
+fn add_to_sums(sums: &mut [u32; 3], color: u32, add: bool) {
+    let channels = [(color >> 16) & 0xFF, (color >> 8) & 0xFF, color & 0xFF];
+    for (sum, channel) in sums.iter_mut().zip(channels.iter()) {
+        if add {
+            *sum += channel;
+        } else {
+            *sum -= channel;
+        }
+    }
+}
+
+/// Source pixel for every pixel of a `width`x`height` image bulged by `amount`
+fn barrel_map(width: usize, height: usize, amount: f32) -> Vec<u32> {
+    let mut map = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            // -1 to 1 from edge to edge
+            let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+            let distortion = 1.0 + amount * (u * u + v * v);
+            let (su, sv) = (u * distortion, v * distortion);
+            if su.abs() > 1.0 || sv.abs() > 1.0 {
+                map.push(u32::MAX);
+                continue;
+            }
+
+            let sx = (((su + 1.0) / 2.0 * width as f32) as usize).min(width - 1);
+            let sy = (((sv + 1.0) / 2.0 * height as f32) as usize).min(height - 1);
+            map.push((sy * width + sx) as u32);
+        }
+    }
+
+    map
+}
+
+/// Multiplies each channel by `factor`/256
+fn scale_color(color: u32, factor: u32) -> u32 {
+    let r = ((color >> 16) & 0xFF) * factor / 256;
+    let g = ((color >> 8) & 0xFF) * factor / 256;
+    let b = (color & 0xFF) * factor / 256;
+    (r.min(0xFF) << 16) | (g.min(0xFF) << 8) | b.min(0xFF)
+}
+
+fn add_colors(a: u32, b: u32) -> u32 {
+    let r = (((a >> 16) & 0xFF) + ((b >> 16) & 0xFF)).min(0xFF);
+    let g = (((a >> 8) & 0xFF) + ((b >> 8) & 0xFF)).min(0xFF);
+    let bl = ((a & 0xFF) + (b & 0xFF)).min(0xFF);
+    (r << 16) | (g << 8) | bl
+}
+
+/// Weighted average of colors, weights are `(color, weight)` pairs
+fn blend(colors: &[(u32, u32)]) -> u32 {
+    let total: u32 = colors.iter().map(|(_, weight)| weight).sum();
+    let channel = |shift: u32| -> u32 {
+        colors.iter().map(|(color, weight)| ((color >> shift) & 0xFF) * weight).sum::<u32>() / total
+    };
+    (channel(16) << 16) | (channel(8) << 8) | channel(0)
+}
+
+/// hq2x's comparison: colors differ if they're further apart than a threshold in Y, U or V
+fn yuv_differs(a: u32, b: u32) -> bool {
+    if a == b {
+        return false;
+    }
+
+    let (ya, ua, va) = to_yuv(a);
+    let (yb, ub, vb) = to_yuv(b);
+    (ya - yb).abs() > 48 || (ua - ub).abs() > 7 || (va - vb).abs() > 6
+}
+
+fn to_yuv(color: u32) -> (i32, i32, i32) {
+    let r = ((color >> 16) & 0xFF) as i32;
+    let g = ((color >> 8) & 0xFF) as i32;
+    let b = (color & 0xFF) as i32;
+    let y = (r + g + b) >> 2;
+    let u = 128 + ((r - b) >> 2);
+    let v = 128 + ((2 * g - r - b) >> 3);
+    (y, u, v)
+}
@@ -1,75 +1,161 @@
-use minifb::{Window, Key, KeyRepeat};
-
-const KEYBOARD_SIZE: usize = 16;
+#[cfg(feature = "desktop")]
+use minifb::{Window, KeyRepeat};
+
+#[cfg(feature = "desktop")]
+pub use minifb::Key;
+
+pub const KEYBOARD_SIZE: usize = 16;
+
+// chip-8 16 key keypad
+// 1 2 3 4
+// Q W E R
+// A S D F
+// Z X C V
+pub const DEFAULT_KEYS: [&str; KEYBOARD_SIZE] = [
+    "1", "2", "3", "4",
+    "Q", "W", "E", "R",
+    "A", "S", "D", "F",
+    "Z", "X", "C", "V",
+];
+
+// keys that can be bound to the keypad
+const NAMED_KEYS: [Key; 72] = [
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4,
+    Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K,
+    Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V,
+    Key::W, Key::X, Key::Y, Key::Z,
+    Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+    Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+    Key::NumPadDot, Key::NumPadSlash, Key::NumPadAsterisk, Key::NumPadMinus, Key::NumPadPlus,
+    Key::NumPadEnter,
+    Key::Up, Key::Down, Key::Left, Key::Right,
+    Key::Space, Key::Enter, Key::Tab, Key::Backspace,
+    Key::Comma, Key::Period, Key::Slash, Key::Semicolon, Key::Apostrophe, Key::Minus, Key::Equal,
+    Key::LeftBracket, Key::RightBracket, Key::Backslash,
+    Key::LeftShift, Key::RightShift,
+];
+
+// keys the interpreter handles itself when they aren't bound to the keypad
+const INTERPRETER_KEYS: [Key; 8] = [Key::P, Key::M, Key::Escape, Key::F1, Key::F8, Key::F9, Key::F10, Key::F12];
 
 pub struct Keymap {
+    bindings: [Key; KEYBOARD_SIZE],
     keys_pressed: [bool; KEYBOARD_SIZE],
+    // keys held by something other than the keyboard, e.g. a script
+    keys_injected: [bool; KEYBOARD_SIZE],
     awaiting_keypress: bool,
     awaiting_keypress_register: usize,
 
     // interpreter specific
     interpreter_keys_pressed: Vec<Key>,
+    // every key goes to the interpreter instead of the keypad, e.g. while the menu is open
+    capturing: bool,
 }
 
 impl Keymap {
-    pub fn new() -> Self {
+    /// `bindings` are the names of the keyboard keys for each chip-8 key, see `key_from_name`.
+    /// Unknown names are left unbound.
+    pub fn new(bindings: &[String]) -> Self {
+        let mut keys = [Key::Unknown; KEYBOARD_SIZE];
+        for (key, name) in keys.iter_mut().zip(bindings.iter()) {
+            *key = key_from_name(name).unwrap_or(Key::Unknown);
+        }
+
         Keymap {
+            bindings: keys,
             keys_pressed: [false; KEYBOARD_SIZE],
+            keys_injected: [false; KEYBOARD_SIZE],
             interpreter_keys_pressed: Vec::new(),
+            capturing: false,
             awaiting_keypress: false,
             awaiting_keypress_register: 0,
         }
     }
 
+    #[cfg(feature = "desktop")]
     pub fn update(&mut self, window: &Window) {
-        let keys_pressed: Option<Vec<Option<i32>>> = window.get_keys_pressed(KeyRepeat::Yes)
+        if self.capturing {
+            if let Some(keys) = window.get_keys_pressed(KeyRepeat::Yes) {
+                self.interpreter_keys_pressed.extend(keys);
+            }
+            return;
+        }
+
+        let keys_pressed: Option<Vec<Option<usize>>> = window.get_keys_pressed(KeyRepeat::Yes)
             .map(|keys| {
                 keys.into_iter().map(|key| {
-                    let key_pressed = match key {
-                        // chip-8 16 key keypad
-                        // 1 2 3 4
-                        // Q W E R
-                        // A S D D
-                        // Z X C V
-                        Key::Key1 => Some(0),
-                        Key::Key2 => Some(1),
-                        Key::Key3 => Some(2),
-                        Key::Key4 => Some(3),
-                        Key::Q => Some(4),
-                        Key::W => Some(5),
-                        Key::E => Some(6),
-                        Key::R => Some(7),
-                        Key::A => Some(8),
-                        Key::S => Some(9),
-                        Key::D => Some(10),
-                        Key::F => Some(11),
-                        Key::Z => Some(12),
-                        Key::X => Some(13),
-                        Key::C => Some(14),
-                        Key::V => Some(15),
-
+                    // chip-8 16 key keypad, bound to keyboard keys by the [input] config
+                    let key_pressed = self.bindings.iter().position(|binding| *binding == key);
+                    if key_pressed.is_none() {
                         // interpreter specific keys
-                        Key::P | Key::Escape => {
+                        if INTERPRETER_KEYS.contains(&key) {
                             self.interpreter_keys_pressed.push(key);
-                            None
-                        },
-                        _ => None
-                    };
+                        }
+                    }
                     key_pressed
-                }).collect::<Vec<Option<i32>>>()
+                }).collect::<Vec<Option<usize>>>()
             });
 
-        if keys_pressed.is_some() {
-            let keys_pressed = keys_pressed.unwrap();
+        if let Some(keys_pressed) = keys_pressed {
             for (i, key) in self.keys_pressed.iter_mut().enumerate() {
-                let pressed = keys_pressed.contains(&Some(i as i32));
+                let pressed = keys_pressed.contains(&Some(i));
                 *key = pressed;
             }
         }
     }
 
+    /// Like `update` for the terminal renderer, which tells the `held` keys apart from the keys
+    /// `pressed` this frame. Interpreter keys only fire when pressed.
+    pub fn update_from_terminal(&mut self, held: &[Key], pressed: &[Key]) {
+        if self.capturing {
+            self.interpreter_keys_pressed.extend_from_slice(pressed);
+            return;
+        }
+
+        for (binding, key_pressed) in self.bindings.iter().zip(self.keys_pressed.iter_mut()) {
+            *key_pressed = held.contains(binding);
+        }
+
+        for key in pressed.iter() {
+            if !self.bindings.contains(key) && INTERPRETER_KEYS.contains(key) {
+                self.interpreter_keys_pressed.push(*key);
+            }
+        }
+    }
+
+    /// Sends every key pressed to the interpreter while `capturing`, with the keypad released
+    pub fn set_capturing(&mut self, capturing: bool) {
+        self.capturing = capturing;
+        self.keys_pressed = [false; KEYBOARD_SIZE];
+    }
+
+    /// Binds the CHIP-8 key `value` to `key`. The CHIP-8 key `key` was bound to gets `value`'s
+    /// old key, so every CHIP-8 key stays bound.
+    pub fn set_binding(&mut self, value: usize, key: Key) {
+        if let Some(other) = self.bindings.iter().position(|binding| *binding == key) {
+            self.bindings[other] = self.bindings[value];
+        }
+        self.bindings[value] = key;
+    }
+
+    /// Names of the keys bound to each CHIP-8 key, like the `[input]` config
+    pub fn get_binding_names(&self) -> Vec<String> {
+        self.bindings.iter().map(|key| key_name(*key)).collect()
+    }
+
+    /// Sets which CHIP-8 keys are held without going through the keyboard bindings
+    pub fn set_keys_pressed(&mut self, keys: [bool; KEYBOARD_SIZE]) {
+        self.keys_pressed = keys;
+    }
+
+    /// Holds the CHIP-8 keys that are `true` on top of the keyboard, until they're injected again
+    pub fn set_keys_injected(&mut self, keys: [bool; KEYBOARD_SIZE]) {
+        self.keys_injected = keys;
+    }
+
     pub fn is_key_pressed(&self, value: usize) -> bool {
-        self.keys_pressed[value]
+        self.keys_pressed[value] || self.keys_injected[value]
     }
 
     pub fn set_awaiting_keypress(&mut self, value: bool) {
@@ -97,45 +183,60 @@ impl Keymap {
     }
 
     pub fn await_keypress(&mut self) -> Option<u8> {
-        for (i, key) in self.keys_pressed.iter_mut().enumerate() {
-            if *key {
-                self.awaiting_keypress = false;
-                return Some(i as u8);
-            }
-        }
-        None
+        let key = (0..KEYBOARD_SIZE).find(|value| self.is_key_pressed(*value))?;
+        self.awaiting_keypress = false;
+        Some(key as u8)
     }
 
     pub fn map_keys_pressed_to_real_values(&self) -> Vec<Key> {
-        let mut keys = Vec::new();
-        for (i, key) in self.keys_pressed.iter().enumerate() {
-            if *key {
-                let pressed = match i {
-                    0 => Some(Key::Key1),
-                    1 => Some(Key::Key2),
-                    2 => Some(Key::Key3),
-                    3 => Some(Key::Key4),
-                    4 => Some(Key::Q),
-                    5 => Some(Key::W),
-                    6 => Some(Key::E),
-                    7 => Some(Key::R),
-                    8 => Some(Key::A),
-                    9 => Some(Key::S),
-                    10 => Some(Key::D),
-                    11 => Some(Key::F),
-                    12 => Some(Key::Z),
-                    13 => Some(Key::X),
-                    14 => Some(Key::C),
-                    15 => Some(Key::V),
-                    _ => None
-                };
-
-                if pressed.is_some() {
-                    keys.push(pressed.unwrap());
-                }
-            }
-        }
+        self.keys_pressed.iter()
+            .zip(self.bindings.iter())
+            .filter(|(pressed, _)| **pressed)
+            .map(|(_, key)| *key)
+            .collect()
+    }
+}
 
-        keys
+/// The name `key_from_name` knows `key` by, e.g. `Q`, `1` or `NumPad0`
+pub fn key_name(key: Key) -> String {
+    let name = format!("{:?}", key);
+    match name.strip_prefix("Key") {
+        Some(digit) if digit.len() == 1 => digit.to_string(),
+        _ => name,
     }
-}
\ No newline at end of file
+}
+
+/// Whether `key` can be bound to the keypad
+pub fn is_bindable(key: Key) -> bool {
+    NAMED_KEYS.contains(&key)
+}
+
+/// Looks up a keyboard key by its name, e.g. `Q`, `Key1`/`1` or `NumPad0`. Case insensitive.
+pub fn key_from_name(name: &str) -> Option<Key> {
+    let name = if name.len() == 1 && name.chars().all(|c| c.is_ascii_digit()) {
+        format!("Key{}", name)
+    } else {
+        name.to_string()
+    };
+
+    NAMED_KEYS.iter()
+        .find(|key| format!("{:?}", key).eq_ignore_ascii_case(&name))
+        .cloned()
+}
+
+/// The keyboard keys crust8cean knows about when built without minifb, named the same so
+/// bindings mean the same thing either way
+#[cfg(not(feature = "desktop"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    NumPad0, NumPad1, NumPad2, NumPad3, NumPad4, NumPad5, NumPad6, NumPad7, NumPad8, NumPad9,
+    NumPadDot, NumPadSlash, NumPadAsterisk, NumPadMinus, NumPadPlus, NumPadEnter,
+    Up, Down, Left, Right,
+    Space, Enter, Tab, Backspace, Delete, Escape,
+    Comma, Period, Slash, Semicolon, Apostrophe, Minus, Equal, LeftBracket, RightBracket, Backslash,
+    LeftShift, RightShift,
+    F1, F8, F9, F10, F12,
+    Unknown,
+}
@@ -0,0 +1,656 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::chip8::quirks::Quirks;
+use crate::modules::cheat::{Cheat, Comparison, Search};
+use crate::modules::config::{self, MAX_SCALE};
+use crate::modules::input::{self, Key};
+use crate::modules::rom;
+use crate::modules::savestate;
+
+// most items shown at once, the list scrolls to keep the selected one in view
+const VISIBLE_ITEMS: usize = 12;
+// most roms remembered in the recent list
+const MAX_RECENT: usize = 10;
+// most addresses a search lists, it has to be narrowed down further before showing more
+const MAX_FOUND: usize = 100;
+// the search steps listed above the addresses found
+const SEARCH_STEPS: [&str; 6] = ["Equal to", "Changed", "Unchanged", "Increased", "Decreased", "Start over"];
+/// the quirks presets the menu offers, see `Quirks::preset`
+pub const QUIRK_PRESETS: [&str; 3] = ["default", "chip8", "schip"];
+/// instructions per second the speed setting steps through
+pub const SPEEDS: [u32; 13] = [200, 300, 400, 500, 600, 700, 800, 1000, 1500, 2000, 3000, 5000, 10000];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Item {
+    Resume,
+    OpenRom,
+    Recent,
+    SaveState,
+    LoadState,
+    Cheats,
+    Quirks,
+    Palette,
+    Speed,
+    Scale,
+    Keys,
+    Quit,
+}
+
+const MAIN_ITEMS: [Item; 12] = [Item::Resume, Item::OpenRom, Item::Recent, Item::SaveState, Item::LoadState,
+    Item::Cheats, Item::Quirks, Item::Palette, Item::Speed, Item::Scale, Item::Keys, Item::Quit];
+
+/// What the menu was showing when it was opened, and what it changes
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// where the running rom was loaded from, the file browser starts next to it
+    pub rom_path: Option<String>,
+    /// SHA-1 of the running rom, save states are kept per rom
+    pub rom_hash: String,
+    pub quirks: Quirks,
+    /// names of the palettes and the index of the current one
+    pub palettes: Vec<String>,
+    pub palette: usize,
+    pub instructions_per_second: u32,
+    pub scale: u8,
+    /// names of the keys bound to each CHIP-8 key
+    pub keys: Vec<String>,
+    /// memory as the menu was opened, for searching
+    pub memory: Vec<u8>,
+    pub cheats: Vec<Cheat>,
+}
+
+/// Something the interpreter should do for the menu
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Close,
+    /// load the rom at this path, in `rom::read` form
+    OpenRom(String),
+    SaveState(u8),
+    LoadState(u8),
+    SetQuirks(Quirks),
+    SetPalette(String),
+    SetSpeed(u32),
+    SetScale(u8),
+    /// bind a CHIP-8 key to a keyboard key, swapping with the CHIP-8 key it was bound to
+    BindKey(usize, Key),
+    /// replace the rom's cheats
+    SetCheats(Vec<Cheat>),
+    Quit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Dir(PathBuf),
+    Archive(PathBuf),
+    Rom(String),
+}
+
+enum Page {
+    Main,
+    Browse {
+        title: String,
+        entries: Vec<(String, Entry)>,
+    },
+    Recent(Vec<String>),
+    States {
+        save: bool,
+        // whether each slot holds a state, looked up when the page opens rather than every frame
+        used: Vec<bool>,
+    },
+    Keys {
+        // the CHIP-8 key waiting for a keyboard key to be pressed
+        binding: Option<usize>,
+    },
+    Cheats,
+    Search {
+        // the value searched for by "Equal to"
+        value: u8,
+    },
+}
+
+/// Menu for opening roms, save states and settings, navigated with the arrow keys, Enter and
+/// Escape. Drawn by the `Osd` over a paused screen.
+pub struct Menu {
+    open: bool,
+    page: Page,
+    selected: usize,
+    // the main menu item to go back to
+    main_selected: usize,
+    settings: Option<Settings>,
+    // the memory search, kept while the menu is closed so the rom can change memory between steps
+    search: Option<Search>,
+}
+
+impl Default for Menu {
+    fn default() -> Self {
+        Menu::new()
+    }
+}
+
+impl Menu {
+    pub fn new() -> Self {
+        Menu {
+            open: false,
+            page: Page::Main,
+            selected: 0,
+            main_selected: 0,
+            settings: None,
+            search: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Opens the main menu showing `settings`
+    pub fn open(&mut self, settings: Settings) {
+        self.open = true;
+        self.page = Page::Main;
+        self.selected = 0;
+        self.main_selected = 0;
+        self.settings = Some(settings);
+    }
+
+    pub fn close(&mut self) {
+        self.open = false;
+        self.settings = None;
+    }
+
+    /// Handles a key pressed while the menu is open
+    pub fn handle_key(&mut self, key: Key) -> Option<Action> {
+        if !self.open {
+            return None;
+        }
+
+        if let Page::Keys { binding: Some(value) } = self.page {
+            self.page = Page::Keys { binding: None };
+            return match key {
+                Key::Escape => None,
+                key if input::is_bindable(key) => {
+                    self.bind(value, key);
+                    Some(Action::BindKey(value, key))
+                },
+                _ => None,
+            };
+        }
+
+        let items = self.item_count();
+        match key {
+            Key::Up if items > 0 => self.selected = (self.selected + items - 1) % items,
+            Key::Down if items > 0 => self.selected = (self.selected + 1) % items,
+            Key::Left => return self.adjust(-1),
+            Key::Right => return self.adjust(1),
+            Key::Enter => return self.select(),
+            Key::Delete => return self.remove_cheat(),
+            Key::Escape | Key::Backspace => match self.page {
+                Page::Main => {
+                    self.close();
+                    return Some(Action::Close);
+                },
+                _ => self.show_main(),
+            },
+            Key::F1 => {
+                self.close();
+                return Some(Action::Close);
+            },
+            _ => (),
+        }
+        None
+    }
+
+    /// The lines to draw, a title then the items with the selected one marked
+    pub fn lines(&self) -> Vec<String> {
+        if !self.open {
+            return Vec::new();
+        }
+
+        let (title, items) = self.contents();
+        let first = (self.selected + 1).saturating_sub(VISIBLE_ITEMS)
+            .min(items.len().saturating_sub(VISIBLE_ITEMS));
+        let mut lines = vec![title];
+        for (i, item) in items.iter().enumerate().skip(first).take(VISIBLE_ITEMS) {
+            let marker = if i == self.selected { ">" } else { " " };
+            lines.push(format!("{} {}", marker, item));
+        }
+        lines
+    }
+
+    fn contents(&self) -> (String, Vec<String>) {
+        let settings = match &self.settings {
+            Some(settings) => settings,
+            None => return (String::new(), Vec::new()),
+        };
+
+        match &self.page {
+            Page::Main => {
+                let items = MAIN_ITEMS.iter()
+                    .map(|item| match item {
+                        Item::Resume => "Resume".to_string(),
+                        Item::OpenRom => "Open rom".to_string(),
+                        Item::Recent => "Recent roms".to_string(),
+                        Item::SaveState => "Save state".to_string(),
+                        Item::LoadState => "Load state".to_string(),
+                        Item::Cheats => "Cheats".to_string(),
+                        Item::Quirks => format!("Quirks   < {} >", settings.quirks),
+                        Item::Palette => format!("Palette  < {} >", settings.palettes[settings.palette]),
+                        Item::Speed => format!("Speed    < {} IPS >", settings.instructions_per_second),
+                        Item::Scale => format!("Scale    < {}x >", settings.scale),
+                        Item::Keys => "Key bindings".to_string(),
+                        Item::Quit => "Quit".to_string(),
+                    })
+                    .collect();
+                ("Menu".to_string(), items)
+            },
+            Page::Browse { title, entries } => {
+                (title.clone(), entries.iter().map(|(label, _)| label.clone()).collect())
+            },
+            Page::Recent(roms) => {
+                let items = if roms.is_empty() {
+                    vec!["No recent roms".to_string()]
+                } else {
+                    roms.iter().map(|path| file_label(path)).collect()
+                };
+                ("Recent roms".to_string(), items)
+            },
+            Page::States { save, used } => {
+                let items = used.iter()
+                    .enumerate()
+                    .map(|(index, used)| format!("Slot {}  {}", index + 1, if *used { "saved" } else { "empty" }))
+                    .collect();
+                (if *save { "Save state" } else { "Load state" }.to_string(), items)
+            },
+            Page::Keys { binding } => {
+                let items = settings.keys.iter()
+                    .enumerate()
+                    .map(|(value, name)| match binding {
+                        Some(binding) if *binding == value => format!("{:X}  press a key...", value),
+                        _ => format!("{:X}  {}", value, name),
+                    })
+                    .collect();
+                ("Key bindings".to_string(), items)
+            },
+            Page::Cheats => {
+                let mut items = vec!["Search memory".to_string()];
+                items.extend(settings.cheats.iter().map(|cheat| {
+                    format!("{:03X} = {:02X}  {}  {}", cheat.address, cheat.value,
+                            if cheat.enabled { "on " } else { "off" }, cheat.name).trim_end().to_string()
+                }));
+                ("Cheats  Enter toggles, Delete removes".to_string(), items)
+            },
+            Page::Search { value } => {
+                let mut items: Vec<String> = SEARCH_STEPS.iter().map(|step| step.to_string()).collect();
+                items[0] = format!("Equal to   < {:02X} >", value);
+                let found = self.found();
+                items.extend(found.iter().map(|address| {
+                    format!("{:03X} = {:02X}", address, settings.memory[*address as usize])
+                }));
+                let count = self.search.as_ref().map_or(0, |search| search.candidates().len());
+                (format!("Search  {} addresses", count), items)
+            },
+        }
+    }
+
+    /// The addresses the search has narrowed down to, once there are few enough to list
+    fn found(&self) -> &[u16] {
+        match &self.search {
+            Some(search) if search.candidates().len() <= MAX_FOUND => search.candidates(),
+            _ => &[],
+        }
+    }
+
+    fn item_count(&self) -> usize {
+        match &self.page {
+            Page::Main => MAIN_ITEMS.len(),
+            Page::Browse { entries, .. } => entries.len(),
+            Page::Recent(roms) => roms.len(),
+            Page::States { .. } => savestate::SLOTS as usize,
+            Page::Keys { .. } => self.settings.as_ref().map_or(0, |settings| settings.keys.len()),
+            Page::Cheats => 1 + self.settings.as_ref().map_or(0, |settings| settings.cheats.len()),
+            Page::Search { .. } => SEARCH_STEPS.len() + self.found().len(),
+        }
+    }
+
+    fn select(&mut self) -> Option<Action> {
+        let selected = self.selected;
+        let rom = match &self.page {
+            Page::Main => match MAIN_ITEMS[selected] {
+                Item::Resume => {
+                    self.close();
+                    return Some(Action::Close);
+                },
+                Item::OpenRom => {
+                    let rom_path = self.settings.as_ref().and_then(|settings| settings.rom_path.clone());
+                    self.browse(start_dir(rom_path.as_deref()));
+                    return None;
+                },
+                Item::Recent => {
+                    self.show(Page::Recent(recent_roms()));
+                    return None;
+                },
+                Item::SaveState => {
+                    self.show_states(true);
+                    return None;
+                },
+                Item::LoadState => {
+                    self.show_states(false);
+                    return None;
+                },
+                Item::Keys => {
+                    self.show(Page::Keys { binding: None });
+                    return None;
+                },
+                Item::Cheats => {
+                    self.show(Page::Cheats);
+                    return None;
+                },
+                Item::Quit => {
+                    self.close();
+                    return Some(Action::Quit);
+                },
+                _ => return self.adjust(1),
+            },
+            Page::Browse { entries, .. } => match entries.get(selected).map(|(_, entry)| entry.clone()) {
+                Some(Entry::Dir(dir)) => {
+                    self.browse(dir);
+                    return None;
+                },
+                Some(Entry::Archive(path)) => {
+                    self.browse_archive(path);
+                    return None;
+                },
+                Some(Entry::Rom(path)) => path,
+                None => return None,
+            },
+            Page::Recent(roms) => match roms.get(selected) {
+                Some(path) => path.clone(),
+                None => return None,
+            },
+            Page::States { save, .. } => {
+                let slot = selected as u8 + 1;
+                let action = if *save { Action::SaveState(slot) } else { Action::LoadState(slot) };
+                self.close();
+                return Some(action);
+            },
+            Page::Keys { .. } => {
+                self.page = Page::Keys { binding: Some(selected) };
+                return None;
+            },
+            Page::Cheats => return self.select_cheat(selected),
+            Page::Search { value } => return self.search_step(selected, *value),
+        };
+
+        self.close();
+        Some(Action::OpenRom(rom))
+    }
+
+    /// The search memory item starts a search if there isn't one, cheats are switched on or off
+    fn select_cheat(&mut self, selected: usize) -> Option<Action> {
+        if selected == 0 {
+            if self.search.is_none() {
+                self.search = self.settings.as_ref().map(|settings| Search::new(&settings.memory));
+            }
+            self.page = Page::Search { value: 0 };
+            self.selected = 0;
+            return None;
+        }
+
+        let settings = self.settings.as_mut()?;
+        let cheat = settings.cheats.get_mut(selected - 1)?;
+        cheat.enabled = !cheat.enabled;
+        Some(Action::SetCheats(settings.cheats.clone()))
+    }
+
+    /// Narrows the search down or starts it over, found addresses are frozen at their value
+    fn search_step(&mut self, selected: usize, value: u8) -> Option<Action> {
+        let settings = self.settings.as_mut()?;
+        let comparison = match selected {
+            0 => Comparison::Equal(value),
+            1 => Comparison::Changed,
+            2 => Comparison::Unchanged,
+            3 => Comparison::Increased,
+            4 => Comparison::Decreased,
+            5 => {
+                self.search = Some(Search::new(&settings.memory));
+                return None;
+            },
+            _ => {
+                let address = *self.found().get(selected - SEARCH_STEPS.len())?;
+                let settings = self.settings.as_mut()?;
+                let index = match settings.cheats.iter().position(|cheat| cheat.address == address) {
+                    Some(index) => index,
+                    None => {
+                        settings.cheats.push(Cheat::new(address, settings.memory[address as usize]));
+                        settings.cheats.len() - 1
+                    },
+                };
+                let cheats = settings.cheats.clone();
+                self.page = Page::Cheats;
+                self.selected = index + 1;
+                return Some(Action::SetCheats(cheats));
+            },
+        };
+        if let Some(search) = self.search.as_mut() {
+            search.narrow(&settings.memory, comparison);
+        }
+        None
+    }
+
+    fn remove_cheat(&mut self) -> Option<Action> {
+        if let Page::Cheats = self.page {
+            if self.selected > 0 {
+                let settings = self.settings.as_mut()?;
+                settings.cheats.remove(self.selected - 1);
+                self.selected = self.selected.min(settings.cheats.len());
+                return Some(Action::SetCheats(settings.cheats.clone()));
+            }
+        }
+        None
+    }
+
+    /// Steps the selected setting by `step`: settings in the main menu, the value searched for
+    /// and the values of cheats
+    fn adjust(&mut self, step: i32) -> Option<Action> {
+        let item = match self.page {
+            Page::Main => MAIN_ITEMS[self.selected],
+            Page::Search { value } => {
+                if self.selected == 0 {
+                    self.page = Page::Search { value: (value as i32 + step).rem_euclid(256) as u8 };
+                }
+                return None;
+            },
+            Page::Cheats if self.selected > 0 => {
+                let settings = self.settings.as_mut()?;
+                let cheat = &mut settings.cheats[self.selected - 1];
+                cheat.value = (cheat.value as i32 + step).rem_euclid(256) as u8;
+                return Some(Action::SetCheats(settings.cheats.clone()));
+            },
+            _ => return None,
+        };
+        let settings = self.settings.as_mut()?;
+
+        match item {
+            Item::Quirks => {
+                let current = QUIRK_PRESETS.iter().position(|name| Quirks::preset(name) == Some(settings.quirks));
+                let index = match current {
+                    Some(index) => wrap(index, step, QUIRK_PRESETS.len()),
+                    None => 0,
+                };
+                settings.quirks = Quirks::preset(QUIRK_PRESETS[index]).unwrap_or_default();
+                Some(Action::SetQuirks(settings.quirks))
+            },
+            Item::Palette => {
+                settings.palette = wrap(settings.palette, step, settings.palettes.len());
+                Some(Action::SetPalette(settings.palettes[settings.palette].clone()))
+            },
+            Item::Speed => {
+                let current = settings.instructions_per_second;
+                let speed = if step > 0 {
+                    SPEEDS.iter().find(|speed| **speed > current).unwrap_or(&SPEEDS[SPEEDS.len() - 1])
+                } else {
+                    SPEEDS.iter().rev().find(|speed| **speed < current).unwrap_or(&SPEEDS[0])
+                };
+                settings.instructions_per_second = *speed;
+                Some(Action::SetSpeed(*speed))
+            },
+            Item::Scale => {
+                settings.scale = (settings.scale as i32 + step).max(1).min(MAX_SCALE as i32) as u8;
+                Some(Action::SetScale(settings.scale))
+            },
+            _ => None,
+        }
+    }
+
+    /// Mirrors `Keymap::set_binding` on the names shown
+    fn bind(&mut self, value: usize, key: Key) {
+        if let Some(settings) = self.settings.as_mut() {
+            let name = input::key_name(key);
+            if let Some(other) = settings.keys.iter().position(|bound| input::key_from_name(bound) == Some(key)) {
+                settings.keys[other] = settings.keys[value].clone();
+            }
+            settings.keys[value] = name;
+        }
+    }
+
+    fn show(&mut self, page: Page) {
+        if let Page::Main = self.page {
+            self.main_selected = self.selected;
+        }
+        self.page = page;
+        self.selected = 0;
+    }
+
+    fn show_main(&mut self) {
+        self.page = Page::Main;
+        self.selected = self.main_selected;
+    }
+
+    /// Lists the save state slots to save to or load from. Saving closes the menu, so the slots
+    /// are looked up again the next time this is shown.
+    fn show_states(&mut self, save: bool) {
+        let rom_hash = self.settings.as_ref().map_or("", |settings| settings.rom_hash.as_str());
+        let used = (1..=savestate::SLOTS).map(|slot| savestate::is_slot_used(rom_hash, slot)).collect();
+        self.show(Page::States { save, used });
+    }
+
+    /// Lists `dir`: its parent, then directories and zip archives, then everything else as roms
+    fn browse(&mut self, dir: PathBuf) {
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+        if let Ok(read_dir) = fs::read_dir(&dir) {
+            for path in read_dir.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+                let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                if name.starts_with('.') {
+                    continue;
+                }
+                if path.is_dir() {
+                    dirs.push((format!("{}/", name), Entry::Dir(path)));
+                } else if name.to_lowercase().ends_with(".zip") {
+                    dirs.push((format!("{}:", name), Entry::Archive(path)));
+                } else {
+                    files.push((name, Entry::Rom(path.to_string_lossy().into_owned())));
+                }
+            }
+        }
+        dirs.sort_by_key(|entry| entry.0.to_lowercase());
+        files.sort_by_key(|entry| entry.0.to_lowercase());
+
+        let mut entries = Vec::new();
+        if let Some(parent) = dir.parent() {
+            entries.push(("../".to_string(), Entry::Dir(parent.to_path_buf())));
+        }
+        entries.extend(dirs);
+        entries.extend(files);
+        self.show(Page::Browse {
+            title: dir.display().to_string(),
+            entries,
+        });
+    }
+
+    /// Lists the roms in a zip archive as `archive.zip:NAME` paths
+    fn browse_archive(&mut self, path: PathBuf) {
+        let archive = path.to_string_lossy().into_owned();
+        let parent = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+        let mut entries = vec![("../".to_string(), Entry::Dir(parent.clone()))];
+        match rom::read_archive(&archive) {
+            Ok(roms) => entries.extend(roms.into_iter()
+                .map(|rom| (rom.name.clone(), Entry::Rom(format!("{}:{}", archive, rom.name))))),
+            Err(e) => entries.push((format!("Error: {}", e), Entry::Dir(parent))),
+        }
+        self.show(Page::Browse {
+            title: file_label(&archive),
+            entries,
+        });
+    }
+}
+
+/// The roms opened most recently, newest first. Missing or unreadable lists are empty.
+pub fn recent_roms() -> Vec<String> {
+    recent_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Puts `rom_path` at the top of the recent roms, kept in
+/// `$XDG_CONFIG_HOME/crust8cean/recent.json`
+pub fn remember_rom(rom_path: &str) -> Result<(), String> {
+    let path = recent_path()
+        .ok_or_else(|| "can't find the user config directory".to_string())?;
+    let rom_path = absolute_rom_path(rom_path);
+    let mut roms = recent_roms();
+    roms.retain(|rom| *rom != rom_path);
+    roms.insert(0, rom_path);
+    roms.truncate(MAX_RECENT);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create '{}': {}", dir.display(), e))?;
+    }
+    let json = serde_json::to_string_pretty(&roms)
+        .map_err(|e| format!("failed to format recent roms: {}", e))?;
+    fs::write(&path, json)
+        .map_err(|e| format!("failed to write '{}': {}", path.display(), e))
+}
+
+fn recent_path() -> Option<PathBuf> {
+    config::user_config_dir().map(|dir| dir.join("recent.json"))
+}
+
+/// Makes the file part of a rom path absolute so it still works from another directory
+fn absolute_rom_path(rom_path: &str) -> String {
+    let (file, entry) = match rom::split_archive_path(rom_path) {
+        Some((archive, Some(name))) => (archive, Some(name)),
+        _ => (rom_path, None),
+    };
+    let file = fs::canonicalize(file)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| file.to_string());
+    match entry {
+        Some(name) => format!("{}:{}", file, name),
+        None => file,
+    }
+}
+
+/// The directory the rom at `rom_path` is in, or the current directory
+fn start_dir(rom_path: Option<&str>) -> PathBuf {
+    let file = rom_path.map(|path| match rom::split_archive_path(path) {
+        Some((archive, _)) => archive,
+        None => path,
+    });
+    file.and_then(|file| fs::canonicalize(file).ok())
+        .and_then(|path| path.parent().map(Path::to_path_buf))
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// The file name part of a rom path, e.g. `c8games.zip:BRIX`
+fn file_label(rom_path: &str) -> String {
+    Path::new(rom_path).file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| rom_path.to_string())
+}
+
+fn wrap(index: usize, step: i32, len: usize) -> usize {
+    (index as i32 + step).rem_euclid(len as i32) as usize
+}
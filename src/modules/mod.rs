@@ -1,4 +1,22 @@
 pub mod display;
+pub mod filter;
+pub mod palette;
+pub mod osd;
+pub mod menu;
+pub mod terminal;
 pub mod input;
 pub mod audio;
-pub mod config;
\ No newline at end of file
+#[cfg(feature = "desktop")]
+pub mod speaker;
+pub mod capture;
+#[cfg(feature = "desktop")]
+pub mod debugger;
+pub mod record;
+pub mod savestate;
+pub mod cheat;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod config;
+pub mod database;
+pub mod rom;
+pub mod patch;
\ No newline at end of file
@@ -0,0 +1,234 @@
+use std::time::{Duration, Instant};
+
+/// how long messages stay on screen
+pub const MESSAGE_FRAMES: u32 = 120;
+// older messages are dropped past this many
+const MAX_MESSAGES: usize = 4;
+
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+const TEXT_COLOR: u32 = 0xFFFFFF;
+
+// 3x5 font, one byte per row with the leftmost pixel in the third bit. Letters are drawn in
+// upper case and anything missing as '?'.
+const FONT: [(char, [u8; GLYPH_HEIGHT]); 60] = [
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b110, 0b001, 0b010, 0b100, 0b111]),
+    ('3', [0b110, 0b001, 0b010, 0b001, 0b110]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b110, 0b001, 0b110]),
+    ('6', [0b011, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b110]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('\\', [0b100, 0b100, 0b010, 0b001, 0b001]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    ('[', [0b011, 0b010, 0b010, 0b010, 0b011]),
+    (']', [0b110, 0b010, 0b010, 0b010, 0b110]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+    ('"', [0b101, 0b101, 0b000, 0b000, 0b000]),
+    ('#', [0b101, 0b111, 0b101, 0b111, 0b101]),
+    ('<', [0b001, 0b010, 0b100, 0b010, 0b001]),
+    ('>', [0b100, 0b010, 0b001, 0b010, 0b100]),
+    ('*', [0b000, 0b101, 0b010, 0b101, 0b000]),
+    ('|', [0b010, 0b010, 0b010, 0b010, 0b010]),
+];
+
+/// Where on screen a line of the overlay goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+}
+
+/// On-screen display drawn over the emulated screen: transient messages, the FPS/IPS counters
+/// and the pause indicator
+pub struct Osd {
+    // messages and the frames they have left, oldest first
+    messages: Vec<(String, u32)>,
+    show_counters: bool,
+    paused: bool,
+    // lines shown until they're replaced, e.g. by a script each frame
+    overlay: Vec<String>,
+    counters: String,
+    // frames and instructions run since `second_start`, for the counters. The clock is only
+    // read while they're shown, some platforms don't have one.
+    second_start: Option<Instant>,
+    frames: u32,
+    cycles: u64,
+}
+
+impl Osd {
+    pub fn new(show_counters: bool) -> Self {
+        Osd {
+            messages: Vec::new(),
+            show_counters,
+            paused: false,
+            overlay: Vec::new(),
+            counters: String::new(),
+            second_start: None,
+            frames: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Shows `message` for `MESSAGE_FRAMES` frames
+    pub fn message(&mut self, message: &str) {
+        self.messages.push((message.to_string(), MESSAGE_FRAMES));
+        if self.messages.len() > MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+    }
+
+    /// Shows `lines` in the top left, replacing the ones shown before
+    pub fn set_overlay(&mut self, lines: Vec<String>) {
+        self.overlay = lines;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Shows or hides the counters, returning whether they're shown
+    pub fn toggle_counters(&mut self) -> bool {
+        self.show_counters = !self.show_counters;
+        self.second_start = None;
+        self.frames = 0;
+        self.cycles = 0;
+        self.show_counters
+    }
+
+    /// Advances a frame in which `cycles` instructions ran. The counters are updated once a
+    /// second of real time.
+    pub fn tick(&mut self, cycles: u64) {
+        for (_, frames) in self.messages.iter_mut() {
+            *frames -= 1;
+        }
+        self.messages.retain(|(_, frames)| *frames > 0);
+
+        if !self.show_counters {
+            return;
+        }
+        self.frames += 1;
+        self.cycles += cycles;
+        let elapsed = self.second_start.get_or_insert_with(Instant::now).elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let seconds = elapsed.as_secs_f64();
+            self.counters = format!("{:.0} FPS {:.0} IPS", self.frames as f64 / seconds, self.cycles as f64 / seconds);
+            self.second_start = Some(Instant::now());
+            self.frames = 0;
+            self.cycles = 0;
+        }
+    }
+
+    /// The lines currently shown and where they go, top to bottom
+    pub fn lines(&self) -> Vec<(Anchor, String)> {
+        let mut lines = Vec::new();
+        if self.show_counters && !self.counters.is_empty() {
+            lines.push((Anchor::TopLeft, self.counters.clone()));
+        }
+        lines.extend(self.overlay.iter().map(|line| (Anchor::TopLeft, line.clone())));
+        if self.paused {
+            lines.push((Anchor::TopRight, "PAUSED".to_string()));
+        }
+        for (message, _) in self.messages.iter() {
+            lines.push((Anchor::BottomLeft, message.clone()));
+        }
+        lines
+    }
+}
+
+/// Draws `lines` over a `width`x`height` buffer, each on a darkened box to stand out from the
+/// screen. Text is scaled up with the buffer and cut off at its edges.
+pub fn draw(lines: &[(Anchor, String)], buffer: &mut [u32], width: usize, height: usize) {
+    let scale = (height / 128).max(1);
+    let line_height = (GLYPH_HEIGHT + 2) * scale;
+    let bottom_lines = lines.iter().filter(|(anchor, _)| *anchor == Anchor::BottomLeft).count();
+
+    let (mut top_left, mut top_right, mut bottom) = (0, 0, height.saturating_sub(bottom_lines * line_height));
+    for (anchor, text) in lines.iter() {
+        let text_width = (text.chars().count() * (GLYPH_WIDTH + 1) + 1) * scale;
+        let (x, y) = match anchor {
+            Anchor::TopLeft => { top_left += line_height; (0, top_left - line_height) },
+            Anchor::TopRight => { top_right += line_height; (width.saturating_sub(text_width), top_right - line_height) },
+            Anchor::BottomLeft => { bottom += line_height; (0, bottom - line_height) },
+        };
+
+        // backing box, the screen at a quarter of its brightness
+        for row in y..(y + line_height).min(height) {
+            for pixel in buffer[row * width + x..row * width + (x + text_width).min(width)].iter_mut() {
+                *pixel = (*pixel >> 2) & 0x3F3F3F;
+            }
+        }
+
+        for (i, c) in text.chars().enumerate() {
+            let glyph = glyph(c);
+            let left = x + (i * (GLYPH_WIDTH + 1) + 1) * scale;
+            for (glyph_y, bits) in glyph.iter().enumerate() {
+                for glyph_x in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> glyph_x) == 0 {
+                        continue;
+                    }
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            let (px, py) = (left + glyph_x * scale + dx, y + (glyph_y + 1) * scale + dy);
+                            if px < width && py < height {
+                                buffer[py * width + px] = TEXT_COLOR;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    FONT.iter()
+        .find(|(glyph, _)| *glyph == c)
+        .or_else(|| FONT.iter().find(|(glyph, _)| *glyph == '?'))
+        .map(|(_, rows)| *rows)
+        .unwrap_or([0; GLYPH_HEIGHT])
+}
@@ -0,0 +1,131 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::modules::config;
+
+// the solid colors P has always cycled through, drawn on black
+const SOLID_COLORS: [(&str, u32); 14] = [
+    ("white", 0xFFFFFF),
+    ("red", 0xFF0000),
+    ("orange", 0xFF8000),
+    ("yellow", 0xFFFF00),
+    ("light-green", 0x80FF00),
+    ("green", 0x00FF00),
+    ("dark-green", 0x00FF80),
+    ("teal", 0x00FFFF),
+    ("blue", 0x0080FF),
+    ("dark-blue", 0x0000FF),
+    ("purple", 0x7F00FF),
+    ("pink", 0xFF00FF),
+    ("magenta", 0xFF007F),
+    ("gray", 0x808080),
+];
+
+// themes shipped in palettes/
+const BUNDLED_THEMES: [(&str, &str); 4] = [
+    ("amber", include_str!("../../palettes/amber.toml")),
+    ("gameboy", include_str!("../../palettes/gameboy.toml")),
+    ("octo", include_str!("../../palettes/octo.toml")),
+    ("paper", include_str!("../../palettes/paper.toml")),
+];
+
+pub const DEFAULT_PALETTE: &str = "white";
+
+/// The colors the screen is drawn in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub background: u32,
+    pub foreground: u32,
+    /// XO-CHIP draws on two bitplanes, these are the colors for a pixel lit on neither plane, the
+    /// first, the second and both
+    pub planes: [u32; 4],
+}
+
+/// A palette theme file, e.g. `palettes/amber.toml`. The palette is named after the file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PaletteFile {
+    #[serde(deserialize_with = "config::deserialize_color")]
+    background: u32,
+    #[serde(deserialize_with = "config::deserialize_color")]
+    foreground: u32,
+    #[serde(default)]
+    planes: Option<[Color; 4]>,
+}
+
+/// A color in a palette file's list of planes
+#[derive(Deserialize)]
+struct Color(#[serde(deserialize_with = "config::deserialize_color")] u32);
+
+impl Palette {
+    /// A palette with the planes derived from the foreground and background
+    pub fn new(name: &str, background: u32, foreground: u32) -> Self {
+        Palette {
+            name: name.to_string(),
+            background,
+            foreground,
+            planes: [background, foreground, mix(background, foreground), foreground],
+        }
+    }
+
+    pub fn from_toml(name: &str, toml: &str) -> Result<Self, String> {
+        let file: PaletteFile = toml::from_str(toml)
+            .map_err(|e| format!("invalid palette '{}': {}", name, e))?;
+        let mut palette = Palette::new(name, file.background, file.foreground);
+        if let Some(planes) = file.planes {
+            palette.planes = [planes[0].0, planes[1].0, planes[2].0, planes[3].0];
+        }
+        Ok(palette)
+    }
+}
+
+/// Every palette in the order P cycles through them: the solid colors, the bundled themes, then
+/// the user's themes from `$XDG_CONFIG_HOME/crust8cean/palettes/*.toml`
+pub fn load_all() -> Result<Vec<Palette>, String> {
+    let mut palettes = builtin();
+    if let Some(dir) = user_palette_dir() {
+        if dir.is_dir() {
+            palettes.extend(load_dir(&dir)?);
+        }
+    }
+
+    Ok(palettes)
+}
+
+pub fn builtin() -> Vec<Palette> {
+    let solid = SOLID_COLORS.iter()
+        .map(|(name, color)| Palette::new(name, 0x000000, *color));
+    let themes = BUNDLED_THEMES.iter()
+        .map(|(name, toml)| Palette::from_toml(name, toml).expect("bundled palettes are valid"));
+    solid.chain(themes).collect()
+}
+
+pub fn user_palette_dir() -> Option<PathBuf> {
+    config::user_config_dir().map(|dir| dir.join("palettes"))
+}
+
+fn load_dir(dir: &Path) -> Result<Vec<Palette>, String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("failed to read palettes from '{}': {}", dir.display(), e))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+        .collect();
+    paths.sort();
+
+    paths.iter()
+        .map(|path| {
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            let toml = fs::read_to_string(path)
+                .map_err(|e| format!("failed to read palette '{}': {}", path.display(), e))?;
+            Palette::from_toml(&name, &toml)
+        })
+        .collect()
+}
+
+/// Average of two colors
+fn mix(a: u32, b: u32) -> u32 {
+    let channel = |shift: u32| (((a >> shift) & 0xFF) + ((b >> shift) & 0xFF)) / 2;
+    (channel(16) << 16) | (channel(8) << 8) | channel(0)
+}
@@ -0,0 +1,221 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::modules::rom;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+// source, target and patch CRC32s
+const BPS_FOOTER_SIZE: usize = 12;
+
+/// Extensions of the patches looked for next to a rom
+pub const EXTENSIONS: [&str; 2] = ["ips", "bps"];
+
+/// The patch applied to a rom when there's no `--patch`: a file named like the rom with an
+/// `.ips` or `.bps` extension, next to the rom or, for roms in zip archives, next to the archive.
+/// e.g. `roms/BRIX.ips` for `roms/c8games.zip:BRIX`.
+pub fn find_patch(rom_path: &str) -> Option<PathBuf> {
+    let rom_file = match rom::split_archive_path(rom_path) {
+        Some((archive, Some(name))) => Path::new(archive).with_file_name(name.rsplit('/').next().unwrap_or(name)),
+        Some((archive, None)) => PathBuf::from(archive),
+        None => PathBuf::from(rom_path),
+    };
+    EXTENSIONS.iter()
+        .map(|extension| rom_file.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+/// Reads the patch at `path` and applies it to `rom`
+pub fn apply_file(rom: &[u8], path: &Path) -> Result<Vec<u8>, String> {
+    let patch = fs::read(path)
+        .map_err(|e| format!("failed to read patch '{}': {}", path.display(), e))?;
+    apply(rom, &patch)
+        .map_err(|e| format!("can't apply patch '{}': {}", path.display(), e))
+}
+
+/// Applies an IPS or BPS patch to `rom`, telling them apart by their headers
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err("not an IPS or BPS patch".to_string())
+    }
+}
+
+/// IPS patches are records of bytes to write at an offset, or runs of one byte, ending with `EOF`
+/// and optionally the size to truncate the rom to. Writes past the end grow the rom.
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let mut target = rom.to_vec();
+    let mut reader = Reader::new(&patch[IPS_MAGIC.len()..]);
+    loop {
+        let offset = reader.take(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        let offset = (offset[0] as usize) << 16 | (offset[1] as usize) << 8 | offset[2] as usize;
+        let size = reader.u16_be()? as usize;
+        let data = if size == 0 {
+            let run = reader.u16_be()? as usize;
+            vec![reader.byte()?; run]
+        } else {
+            reader.take(size)?.to_vec()
+        };
+
+        if target.len() < offset + data.len() {
+            target.resize(offset + data.len(), 0);
+        }
+        target[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    match reader.remaining() {
+        0 => (),
+        3 => {
+            let size = reader.take(3)?;
+            target.truncate((size[0] as usize) << 16 | (size[1] as usize) << 8 | size[2] as usize);
+        },
+        _ => return Err("unexpected data after the end of the patch".to_string()),
+    }
+    Ok(target)
+}
+
+/// BPS patches build the patched rom from copies out of the rom, the patch and what's been built
+/// so far. The CRC32s of the rom, the patched rom and the patch itself are all checked, so a
+/// patch for another rom or a damaged one is rejected.
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_SIZE {
+        return Err("patch is truncated".to_string());
+    }
+    let (body, footer) = patch.split_at(patch.len() - BPS_FOOTER_SIZE);
+    let source_crc = u32_le(&footer[0..4]);
+    let target_crc = u32_le(&footer[4..8]);
+    let patch_crc = u32_le(&footer[8..12]);
+    if crc32(&patch[..patch.len() - 4]) != patch_crc {
+        return Err("patch is damaged, its checksum doesn't match".to_string());
+    }
+
+    let mut reader = Reader::new(&body[BPS_MAGIC.len()..]);
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.take(metadata_size)?;
+    if source_size != rom.len() || crc32(rom) != source_crc {
+        return Err("patch is for a different rom, the rom's checksum doesn't match".to_string());
+    }
+
+    let mut target: Vec<u8> = Vec::new();
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    while reader.remaining() > 0 {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+        let start = target.len();
+        if length > target_size - start {
+            return Err("patch writes past the end of the patched rom".to_string());
+        }
+        match action & 3 {
+            // source read: the rom at the same offset
+            0 => target.extend_from_slice(rom.get(start..).and_then(|rest| rest.get(..length))
+                .ok_or_else(out_of_bounds)?),
+            // target read: bytes from the patch
+            1 => target.extend_from_slice(reader.take(length)?),
+            // source copy: the rom from a relative offset
+            2 => {
+                source_offset = relative_offset(source_offset, reader.number()?)?;
+                target.extend_from_slice(rom.get(source_offset..).and_then(|rest| rest.get(..length))
+                    .ok_or_else(out_of_bounds)?);
+                source_offset += length;
+            },
+            // target copy: what's been built so far, byte by byte since it can overlap what it writes
+            _ => {
+                target_offset = relative_offset(target_offset, reader.number()?)?;
+                for _ in 0..length {
+                    let byte = *target.get(target_offset).ok_or_else(out_of_bounds)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            },
+        }
+    }
+
+    if target.len() != target_size || crc32(&target) != target_crc {
+        return Err("patched rom's checksum doesn't match".to_string());
+    }
+    Ok(target)
+}
+
+/// Moves `offset` by a BPS relative offset, the lowest bit of which is the sign
+fn relative_offset(offset: usize, data: usize) -> Result<usize, String> {
+    let distance = data >> 1;
+    if data & 1 == 0 {
+        offset.checked_add(distance).ok_or_else(out_of_bounds)
+    } else {
+        offset.checked_sub(distance).ok_or_else(out_of_bounds)
+    }
+}
+
+fn out_of_bounds() -> String {
+    "patch copies from outside the rom".to_string()
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16 | u32::from(bytes[3]) << 24
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, position: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn take(&mut self, size: usize) -> Result<&'a [u8], String> {
+        if size > self.remaining() {
+            return Err("patch is truncated".to_string());
+        }
+        let bytes = &self.data[self.position..self.position + size];
+        self.position += size;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16_be(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+    }
+
+    /// A BPS variable length number, 7 bits a byte with the last byte's top bit set
+    fn number(&mut self) -> Result<usize, String> {
+        let too_big = || "patch has a number too big to read".to_string();
+        let mut number: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()?;
+            number = ((byte & 0x7F) as usize).checked_mul(shift)
+                .and_then(|value| number.checked_add(value))
+                .ok_or_else(too_big)?;
+            if byte & 0x80 != 0 {
+                return Ok(number);
+            }
+            shift = shift.checked_mul(128).ok_or_else(too_big)?;
+            number = number.checked_add(shift).ok_or_else(too_big)?;
+        }
+    }
+}
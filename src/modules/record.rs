@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use crate::chip8::cpu::FRAMES_PER_SECOND;
+use crate::modules::audio::{self, Beeper, Tone};
+use crate::modules::capture;
+use crate::modules::config::AudioConfig;
+use crate::modules::filter::Image;
+
+/// What recordings are saved as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    /// an animated gif, frames that don't change are merged into one with a longer delay
+    Gif,
+    /// a directory of numbered pngs, one per frame at 60 fps
+    Png,
+    /// an uncompressed YUV4MPEG2 stream at 60 fps that ffmpeg and friends can encode
+    Y4m,
+}
+
+impl FromStr for RecordFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gif" => Ok(RecordFormat::Gif),
+            "png" => Ok(RecordFormat::Png),
+            "y4m" => Ok(RecordFormat::Y4m),
+            _ => Err(format!("unknown record format '{}', expected one of gif/png/y4m", s)),
+        }
+    }
+}
+
+impl fmt::Display for RecordFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RecordFormat::Gif => "gif",
+            RecordFormat::Png => "png",
+            RecordFormat::Y4m => "y4m",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+enum VideoWriter {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        // the last frame that differed from the one before it, written once we know how long it lasted
+        pending: Option<(Image, u64)>,
+    },
+    Png {
+        dir: PathBuf,
+    },
+    Y4m {
+        writer: BufWriter<File>,
+    },
+}
+
+/// Renders the beeper to a wav one frame at a time, `SAMPLES_PER_FRAME` samples each
+pub struct WavRecorder {
+    wav: hound::WavWriter<BufWriter<File>>,
+    path: PathBuf,
+    beeper: Beeper,
+    samples: Vec<i16>,
+    frames: u64,
+}
+
+impl WavRecorder {
+    /// Creates the wav at `path`, the beeper sounds like `audio` says
+    pub fn create(path: &Path, audio: &AudioConfig) -> Result<Self, String> {
+        let wav = hound::WavWriter::new(BufWriter::new(create(path)?), audio::wav_spec())
+            .map_err(|e| write_error(path, e))?;
+        Ok(WavRecorder {
+            wav,
+            path: path.to_path_buf(),
+            beeper: Beeper::new(audio),
+            samples: Vec::with_capacity(audio::SAMPLES_PER_FRAME),
+            frames: 0,
+        })
+    }
+
+    /// Adds a frame of the beeper playing `tone`
+    pub fn add_frame(&mut self, tone: Tone) -> Result<(), String> {
+        self.samples.clear();
+        self.beeper.render_frame(tone, &mut self.samples);
+        for sample in self.samples.iter() {
+            self.wav.write_sample(*sample)
+                .map_err(|e| write_error(&self.path, e))?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Finishes the wav, returning its path
+    pub fn finish(self) -> Result<PathBuf, String> {
+        let WavRecorder { wav, path, .. } = self;
+        wav.finalize()
+            .map_err(|e| write_error(&path, e))?;
+        Ok(path)
+    }
+
+    pub fn get_frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+/// Records every frame along with the beeper, until `finish` is called
+pub struct Recorder {
+    video: VideoWriter,
+    video_path: PathBuf,
+    wav: WavRecorder,
+    frames: u64,
+}
+
+impl Recorder {
+    /// Starts a recording in `dir` named after the current time. Frames have to be
+    /// `width`x`height`, the beeper sounds like `audio` says.
+    pub fn start(dir: &Path, format: RecordFormat, width: usize, height: usize, audio: &AudioConfig)
+        -> Result<Self, String> {
+        let wav_path = capture::timestamped_path(dir, "wav")?;
+        let video_path = wav_path.with_extension(match format {
+            RecordFormat::Gif => "gif",
+            RecordFormat::Png => "",
+            RecordFormat::Y4m => "y4m",
+        });
+
+        let video = match format {
+            RecordFormat::Gif => {
+                let mut encoder = gif::Encoder::new(BufWriter::new(create(&video_path)?),
+                                                    width as u16, height as u16, &[0, 0, 0])
+                    .map_err(|e| write_error(&video_path, e))?;
+                gif::SetParameter::set(&mut encoder, gif::Repeat::Infinite)
+                    .map_err(|e| write_error(&video_path, e))?;
+                VideoWriter::Gif {
+                    encoder,
+                    pending: None,
+                }
+            },
+            RecordFormat::Png => {
+                fs::create_dir_all(&video_path)
+                    .map_err(|e| format!("failed to create '{}': {}", video_path.display(), e))?;
+                VideoWriter::Png {
+                    dir: video_path.clone(),
+                }
+            },
+            RecordFormat::Y4m => {
+                let mut writer = BufWriter::new(create(&video_path)?);
+                // full range 4:4:4, so pixels map to exactly one sample in each plane
+                writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444 XCOLORRANGE=FULL",
+                       width, height, FRAMES_PER_SECOND)
+                    .map_err(|e| write_error(&video_path, e))?;
+                VideoWriter::Y4m {
+                    writer,
+                }
+            },
+        };
+
+        Ok(Recorder {
+            video,
+            video_path,
+            wav: WavRecorder::create(&wav_path, audio)?,
+            frames: 0,
+        })
+    }
+
+    /// Adds one frame, with what the beeper played during it
+    pub fn add_frame(&mut self, image: Image, tone: Tone) -> Result<(), String> {
+        self.wav.add_frame(tone)?;
+
+        let frame = self.frames;
+        self.frames += 1;
+        let video_path = &self.video_path;
+        match &mut self.video {
+            VideoWriter::Gif { encoder, pending } => {
+                if let Some((previous, _)) = pending {
+                    if previous.pixels == image.pixels {
+                        return Ok(());
+                    }
+                }
+                if let Some((previous, start)) = pending.take() {
+                    write_gif_frame(encoder, &previous, start, frame)
+                        .map_err(|e| write_error(video_path, e))?;
+                }
+                *pending = Some((image, frame));
+            },
+            VideoWriter::Png { dir } => {
+                capture::save_png(&dir.join(format!("{:06}.png", frame)), &image)?;
+            },
+            VideoWriter::Y4m { writer } => {
+                write_y4m_frame(writer, &image)
+                    .map_err(|e| write_error(video_path, e))?;
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Flushes everything to disk, returning the paths of the video and the audio
+    pub fn finish(self) -> Result<(PathBuf, PathBuf), String> {
+        let Recorder { video, video_path, wav, frames } = self;
+        match video {
+            VideoWriter::Gif { mut encoder, pending } => {
+                if let Some((previous, start)) = pending {
+                    write_gif_frame(&mut encoder, &previous, start, frames)
+                        .map_err(|e| write_error(&video_path, e))?;
+                }
+                // the trailer is written when the encoder is dropped
+            },
+            VideoWriter::Png { .. } => (),
+            VideoWriter::Y4m { mut writer } => {
+                writer.flush()
+                    .map_err(|e| write_error(&video_path, e))?;
+            },
+        }
+
+        let wav_path = wav.finish()?;
+        Ok((video_path, wav_path))
+    }
+
+    pub fn get_frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+/// Writes a frame shown from frame `start` until frame `end`. Gif delays are in hundredths of a
+/// second, so they're rounded against the total time to keep the recording from drifting.
+fn write_gif_frame<W: Write>(encoder: &mut gif::Encoder<W>, image: &Image, start: u64, end: u64)
+    -> std::io::Result<()> {
+    let centiseconds = |frame: u64| (frame * 100 + FRAMES_PER_SECOND as u64 / 2) / FRAMES_PER_SECOND as u64;
+    let delay = (centiseconds(end) - centiseconds(start)).min(u16::MAX as u64) as u16;
+
+    let mut colors: HashMap<u32, u8> = HashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(image.pixels.len());
+    for pixel in image.pixels.iter() {
+        let index = match colors.get(pixel) {
+            Some(index) => *index,
+            None if colors.len() < 256 => {
+                let index = colors.len() as u8;
+                colors.insert(*pixel, index);
+                palette.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8]);
+                index
+            },
+            // more colors than a gif frame can hold, let the encoder quantize it
+            None => break,
+        };
+        indices.push(index);
+    }
+
+    let mut frame = if indices.len() == image.pixels.len() {
+        gif::Frame::from_palette_pixels(image.width as u16, image.height as u16, &indices, &palette, None)
+    } else {
+        let rgb: Vec<u8> = image.pixels.iter()
+            .flat_map(|pixel| vec![(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8])
+            .collect();
+        gif::Frame::from_rgb(image.width as u16, image.height as u16, &rgb)
+    };
+    frame.delay = delay;
+    encoder.write_frame(&frame)
+}
+
+fn write_y4m_frame<W: Write>(writer: &mut W, image: &Image) -> std::io::Result<()> {
+    let mut planes: Vec<Vec<u8>> = (0..3).map(|_| Vec::with_capacity(image.pixels.len())).collect();
+    for pixel in image.pixels.iter() {
+        let r = ((pixel >> 16) & 0xFF) as f32;
+        let g = ((pixel >> 8) & 0xFF) as f32;
+        let b = (pixel & 0xFF) as f32;
+        // full range BT.601
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+        planes[0].push(y.round() as u8);
+        planes[1].push((128.0 + (b - y) * 0.564).round().clamp(0.0, 255.0) as u8);
+        planes[2].push((128.0 + (r - y) * 0.713).round().clamp(0.0, 255.0) as u8);
+    }
+
+    writer.write_all(b"FRAME\n")?;
+    for plane in planes.iter() {
+        writer.write_all(plane)?;
+    }
+    Ok(())
+}
+
+fn create(path: &Path) -> Result<File, String> {
+    File::create(path)
+        .map_err(|e| format!("failed to create '{}': {}", path.display(), e))
+}
+
+fn write_error<E: fmt::Display>(path: &Path, e: E) -> String {
+    format!("failed to write '{}': {}", path.display(), e)
+}
@@ -0,0 +1,129 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zip::ZipArchive;
+use crate::chip8::cpu::Cpu;
+
+const ARCHIVE_EXTENSION: &str = ".zip";
+
+/// A rom read from disk, either a plain file or an entry in a zip archive
+pub struct Rom {
+    /// file name of the rom, without the archive path for roms in archives, e.g. `BRIX` for
+    /// `roms/c8games.zip:BRIX`
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+pub struct ArchiveEntry {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Reads a rom from `path`, which is either a plain file, `archive.zip:NAME` to pick the entry
+/// `NAME` from an archive, or just `archive.zip` for archives that hold a single rom. Roms too
+/// big to fit in memory are rejected.
+pub fn read(path: &str) -> Result<Rom, String> {
+    let rom = read_unchecked(path)?;
+    Cpu::check_program(&rom.data)
+        .map_err(|e| format!("can't load '{}': {}", path, e))?;
+    Ok(rom)
+}
+
+fn read_unchecked(path: &str) -> Result<Rom, String> {
+    let (archive_path, entry_name) = match split_archive_path(path) {
+        Some(split) => split,
+        None => {
+            let data = read_file(path)?;
+            let name = Path::new(path).file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.to_string());
+            return Ok(Rom { name, data });
+        },
+    };
+
+    let entries = read_archive(archive_path)?;
+    let entry = match entry_name {
+        Some(name) => find_entry(entries, name).ok_or_else(|| {
+            format!("'{}' has no rom named '{}', list its roms with `crust8cean list {}`",
+                    archive_path, name, archive_path)
+        })?,
+        None if entries.len() == 1 => entries.into_iter().next().unwrap(),
+        None => return Err(format!("'{}' holds {} roms, pick one with {}:NAME or list them with `crust8cean list {}`",
+                                   archive_path, entries.len(), archive_path, archive_path)),
+    };
+
+    Ok(Rom {
+        name: entry_file_name(&entry.name).to_string(),
+        data: entry.data,
+    })
+}
+
+/// Splits `archive.zip:NAME` into the archive path and entry name. Returns `None` for paths
+/// that don't point into a zip archive.
+pub fn split_archive_path(path: &str) -> Option<(&str, Option<&str>)> {
+    if is_archive(path) {
+        return Some((path, None));
+    }
+
+    // split on the last colon so windows drive letters don't count
+    let colon = path.rfind(':')?;
+    let (archive_path, name) = (&path[..colon], &path[colon + 1..]);
+    if is_archive(archive_path) && !name.is_empty() {
+        Some((archive_path, Some(name)))
+    } else {
+        None
+    }
+}
+
+/// Every file in a zip archive, in the order they're stored
+pub fn read_archive(path: &str) -> Result<Vec<ArchiveEntry>, String> {
+    let file = File::open(path)
+        .map_err(|e| format!("failed to open archive '{}': {}", path, e))?;
+    let mut archive = ZipArchive::new(file)
+        .map_err(|e| format!("failed to read archive '{}': {}", path, e))?;
+
+    let mut entries = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)
+            .map_err(|e| format!("failed to read archive '{}': {}", path, e))?;
+        if file.is_dir() {
+            continue;
+        }
+
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)
+            .map_err(|e| format!("failed to extract '{}' from '{}': {}", file.name(), path, e))?;
+        entries.push(ArchiveEntry {
+            name: file.name().to_string(),
+            data,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn is_archive(path: &str) -> bool {
+    path.to_lowercase().ends_with(ARCHIVE_EXTENSION)
+}
+
+/// Matches the full entry name first, then case insensitively on the file name so `brix` finds
+/// `games/BRIX`
+fn find_entry(entries: Vec<ArchiveEntry>, name: &str) -> Option<ArchiveEntry> {
+    let position = entries.iter().position(|entry| entry.name == name)
+        .or_else(|| entries.iter().position(|entry| entry_file_name(&entry.name).eq_ignore_ascii_case(name)))?;
+    entries.into_iter().nth(position)
+}
+
+fn entry_file_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    let mut file = File::open(Path::new(path))
+        .map_err(|e| format!("failed to open rom '{}': {}", path, e))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)
+        .map_err(|e| format!("failed to read rom '{}': {}", path, e))?;
+
+    Ok(data)
+}
@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::PathBuf;
+use crate::modules::config;
+
+/// Save state slots per rom, numbered from 1
+pub const SLOTS: u8 = 8;
+
+/// `$XDG_CONFIG_HOME/crust8cean/states/<rom sha1>-<slot>.state`, so states follow the rom
+/// wherever it's loaded from
+pub fn slot_path(rom_hash: &str, slot: u8) -> Option<PathBuf> {
+    config::user_config_dir().map(|dir| dir.join("states").join(format!("{}-{}.state", rom_hash, slot)))
+}
+
+pub fn is_slot_used(rom_hash: &str, slot: u8) -> bool {
+    slot_path(rom_hash, slot).is_some_and(|path| path.is_file())
+}
+
+/// Writes `state` from `Cpu::save_state` to a slot, returning where it went
+pub fn save_slot(rom_hash: &str, slot: u8, state: &[u8]) -> Result<PathBuf, String> {
+    let path = slot_path(rom_hash, slot)
+        .ok_or_else(|| "can't find the user config directory to save states in".to_string())?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create '{}': {}", dir.display(), e))?;
+    }
+    fs::write(&path, state)
+        .map_err(|e| format!("failed to write '{}': {}", path.display(), e))?;
+    Ok(path)
+}
+
+pub fn load_slot(rom_hash: &str, slot: u8) -> Result<Vec<u8>, String> {
+    match slot_path(rom_hash, slot) {
+        Some(path) if path.is_file() => fs::read(&path)
+            .map_err(|e| format!("failed to read '{}': {}", path.display(), e)),
+        _ => Err(format!("slot {} is empty", slot)),
+    }
+}
@@ -0,0 +1,276 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, AST, INT};
+use crate::modules::input::KEYBOARD_SIZE;
+
+// operations a callback may run before it's stopped, so a runaway loop can't hang the emulator
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+/// The parts of the cpu a script can see and change. The cpu copies itself in before callbacks
+/// run and takes back whatever they changed.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptView {
+    pub memory: Vec<u8>,
+    pub registers: [u8; 16],
+    pub i: usize,
+    pub program_counter: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+/// What scripts register and ask for, shared with the functions they call
+#[derive(Default)]
+struct State {
+    view: ScriptView,
+    // the view was changed and needs copying back
+    changed: bool,
+    frame_hooks: Vec<FnPtr>,
+    pc_hooks: HashMap<u16, Vec<FnPtr>>,
+    write_hooks: HashMap<u16, Vec<FnPtr>>,
+    keys: [bool; KEYBOARD_SIZE],
+    overlay: Vec<String>,
+    stopped: bool,
+    // frames ended since the script was loaded
+    frame: u64,
+}
+
+/// A Rhai script driving the interpreter, for bots, cheats, auto-splitters and test assertions.
+/// Scripts register callbacks when they're loaded:
+/// - `on_frame(|| ...)` after every frame
+/// - `on_pc(address, || ...)` before the instruction at `address` runs
+/// - `on_write(address, |address, value| ...)` after an instruction writes to `address`
+///
+/// and can call `peek`/`poke`, `reg`/`set_reg`, `index`/`set_index`, `pc`/`set_pc`,
+/// `delay_timer`/`set_delay_timer`, `sound_timer`/`set_sound_timer`, `frame` (frames since loading),
+/// `press`/`release` to hold CHIP-8 keys, `overlay` to show a line of text this frame and `stop`
+/// to stop the interpreter. Errors, e.g. from `throw`, stop the script.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    state: Rc<RefCell<State>>,
+}
+
+impl Script {
+    /// Compiles the script at `path`, `run` runs its top level
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let state = Rc::new(RefCell::new(State::default()));
+        let engine = build_engine(&state);
+        let ast = engine.compile_file(path.to_path_buf())
+            .map_err(|e| format!("failed to load script '{}': {}", path.display(), e))?;
+        Ok(Script { engine, ast, state })
+    }
+
+    /// Runs the top level of the script, which registers its callbacks
+    pub fn run(&mut self, view: ScriptView) -> Result<Option<ScriptView>, String> {
+        self.state.borrow_mut().view = view;
+        self.engine.run_ast(&self.ast)
+            .map_err(|e| format!("script error: {}", e))?;
+        Ok(self.take_changes())
+    }
+
+    pub fn has_pc_hook(&self, address: u16) -> bool {
+        self.state.borrow().pc_hooks.contains_key(&address)
+    }
+
+    pub fn has_write_hook(&self, address: u16) -> bool {
+        self.state.borrow().write_hooks.contains_key(&address)
+    }
+
+    /// Calls the `on_frame` callbacks, returning the view if they changed it
+    pub fn frame_end(&mut self, view: ScriptView) -> Result<Option<ScriptView>, String> {
+        self.state.borrow_mut().frame += 1;
+        let hooks = self.state.borrow().frame_hooks.clone();
+        self.call(view, &hooks, ())
+    }
+
+    /// Calls the `on_pc` callbacks for `address`
+    pub fn pc_hit(&mut self, view: ScriptView, address: u16) -> Result<Option<ScriptView>, String> {
+        let hooks = self.state.borrow().pc_hooks.get(&address).cloned().unwrap_or_default();
+        self.call(view, &hooks, ())
+    }
+
+    /// Calls the `on_write` callbacks for each of `writes`, an address and the value written
+    pub fn memory_written(&mut self, view: ScriptView, writes: &[(u16, u8)]) -> Result<Option<ScriptView>, String> {
+        self.state.borrow_mut().view = view;
+        for (address, value) in writes.iter() {
+            let hooks = self.state.borrow().write_hooks.get(address).cloned().unwrap_or_default();
+            for hook in hooks.iter() {
+                self.call_hook(hook, (*address as INT, *value as INT))?;
+            }
+        }
+        Ok(self.take_changes())
+    }
+
+    /// The CHIP-8 keys the script is holding
+    pub fn get_keys(&self) -> [bool; KEYBOARD_SIZE] {
+        self.state.borrow().keys
+    }
+
+    /// The lines the script showed this frame
+    pub fn take_overlay(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.state.borrow_mut().overlay)
+    }
+
+    /// Whether the script called `stop`
+    pub fn is_stopped(&self) -> bool {
+        self.state.borrow().stopped
+    }
+
+    fn call(&mut self, view: ScriptView, hooks: &[FnPtr], args: impl rhai::FuncArgs + Clone)
+        -> Result<Option<ScriptView>, String> {
+        self.state.borrow_mut().view = view;
+        for hook in hooks.iter() {
+            self.call_hook(hook, args.clone())?;
+        }
+        Ok(self.take_changes())
+    }
+
+    fn call_hook(&self, hook: &FnPtr, args: impl rhai::FuncArgs) -> Result<(), String> {
+        hook.call::<Dynamic>(&self.engine, &self.ast, args)
+            .map(|_| ())
+            .map_err(|e| format!("script error: {}", e))
+    }
+
+    fn take_changes(&mut self) -> Option<ScriptView> {
+        let mut state = self.state.borrow_mut();
+        if state.changed {
+            state.changed = false;
+            Some(std::mem::take(&mut state.view))
+        } else {
+            None
+        }
+    }
+}
+
+fn build_engine(state: &Rc<RefCell<State>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    let s = state.clone();
+    engine.register_fn("on_frame", move |callback: FnPtr| {
+        s.borrow_mut().frame_hooks.push(callback);
+    });
+    let s = state.clone();
+    engine.register_fn("on_pc", move |address: INT, callback: FnPtr| -> Result<(), Box<EvalAltResult>> {
+        let address = check_address(address)?;
+        s.borrow_mut().pc_hooks.entry(address).or_default().push(callback);
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("on_write", move |address: INT, callback: FnPtr| -> Result<(), Box<EvalAltResult>> {
+        let address = check_address(address)?;
+        s.borrow_mut().write_hooks.entry(address).or_default().push(callback);
+        Ok(())
+    });
+
+    let s = state.clone();
+    engine.register_fn("peek", move |address: INT| -> Result<INT, Box<EvalAltResult>> {
+        let address = check_address(address)?;
+        Ok(s.borrow().view.memory[address as usize] as INT)
+    });
+    let s = state.clone();
+    engine.register_fn("poke", move |address: INT, value: INT| -> Result<(), Box<EvalAltResult>> {
+        let address = check_address(address)?;
+        let mut state = s.borrow_mut();
+        state.view.memory[address as usize] = value as u8;
+        state.changed = true;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("reg", move |register: INT| -> Result<INT, Box<EvalAltResult>> {
+        Ok(s.borrow().view.registers[check_register(register)?] as INT)
+    });
+    let s = state.clone();
+    engine.register_fn("set_reg", move |register: INT, value: INT| -> Result<(), Box<EvalAltResult>> {
+        let register = check_register(register)?;
+        let mut state = s.borrow_mut();
+        state.view.registers[register] = value as u8;
+        state.changed = true;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("index", move || s.borrow().view.i as INT);
+    let s = state.clone();
+    engine.register_fn("set_index", move |value: INT| -> Result<(), Box<EvalAltResult>> {
+        let value = check_address(value)?;
+        let mut state = s.borrow_mut();
+        state.view.i = value as usize;
+        state.changed = true;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("pc", move || s.borrow().view.program_counter as INT);
+    let s = state.clone();
+    engine.register_fn("set_pc", move |value: INT| -> Result<(), Box<EvalAltResult>> {
+        let value = check_address(value)?;
+        let mut state = s.borrow_mut();
+        state.view.program_counter = value;
+        state.changed = true;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("delay_timer", move || s.borrow().view.delay_timer as INT);
+    let s = state.clone();
+    engine.register_fn("set_delay_timer", move |value: INT| {
+        let mut state = s.borrow_mut();
+        state.view.delay_timer = value as u8;
+        state.changed = true;
+    });
+    let s = state.clone();
+    engine.register_fn("sound_timer", move || s.borrow().view.sound_timer as INT);
+    let s = state.clone();
+    engine.register_fn("set_sound_timer", move |value: INT| {
+        let mut state = s.borrow_mut();
+        state.view.sound_timer = value as u8;
+        state.changed = true;
+    });
+    let s = state.clone();
+    engine.register_fn("frame", move || s.borrow().frame as INT);
+
+    let s = state.clone();
+    engine.register_fn("press", move |key: INT| -> Result<(), Box<EvalAltResult>> {
+        s.borrow_mut().keys[check_key(key)?] = true;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("release", move |key: INT| -> Result<(), Box<EvalAltResult>> {
+        s.borrow_mut().keys[check_key(key)?] = false;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("overlay", move |text: &str| {
+        s.borrow_mut().overlay.push(text.to_string());
+    });
+    let s = state.clone();
+    engine.register_fn("stop", move || {
+        s.borrow_mut().stopped = true;
+    });
+
+    engine
+}
+
+fn check_address(address: INT) -> Result<u16, Box<EvalAltResult>> {
+    if (0..0x1000).contains(&address) {
+        Ok(address as u16)
+    } else {
+        Err(format!("address 0x{:X} is outside memory", address).into())
+    }
+}
+
+fn check_register(register: INT) -> Result<usize, Box<EvalAltResult>> {
+    if (0..16).contains(&register) {
+        Ok(register as usize)
+    } else {
+        Err(format!("there's no register V{:X}", register).into())
+    }
+}
+
+fn check_key(key: INT) -> Result<usize, Box<EvalAltResult>> {
+    if (0..KEYBOARD_SIZE as INT).contains(&key) {
+        Ok(key as usize)
+    } else {
+        Err(format!("there's no key {:X}", key).into())
+    }
+}
@@ -0,0 +1,128 @@
+use std::os::raw::c_void;
+use crate::modules::audio::{Beeper, Tone, SAMPLES_PER_FRAME, SAMPLE_RATE};
+use crate::modules::config::AudioConfig;
+
+// frames queued ahead of what's playing at most, about 100ms
+const BUFFERS: usize = 6;
+// frames queued before the speakers start, so one late frame doesn't cut the sound off
+const PREBUFFER: usize = 3;
+
+const AL_NO_ERROR: i32 = 0;
+const AL_FORMAT_MONO16: i32 = 0x1101;
+const AL_SOURCE_STATE: i32 = 0x1010;
+const AL_PLAYING: i32 = 0x1012;
+const AL_BUFFERS_PROCESSED: i32 = 0x1016;
+
+// ears only plays files, so the samples go to OpenAL directly. ears links it and opens the device.
+extern "C" {
+    fn alGenSources(n: i32, sources: *mut u32);
+    fn alDeleteSources(n: i32, sources: *const u32);
+    fn alGenBuffers(n: i32, buffers: *mut u32);
+    fn alDeleteBuffers(n: i32, buffers: *const u32);
+    fn alBufferData(buffer: u32, format: i32, data: *const c_void, size: i32, frequency: i32);
+    fn alSourceQueueBuffers(source: u32, n: i32, buffers: *const u32);
+    fn alSourceUnqueueBuffers(source: u32, n: i32, buffers: *mut u32);
+    fn alSourcePlay(source: u32);
+    fn alSourceStop(source: u32);
+    fn alGetSourcei(source: u32, param: i32, value: *mut i32);
+    fn alGetError() -> i32;
+}
+
+/// Plays the beeper through the speakers a frame at a time. The beeper and the OpenAL source
+/// live as long as the speaker, so waves, patterns and the envelope carry on from one frame to
+/// the next instead of restarting.
+pub struct Speaker {
+    beeper: Beeper,
+    source: u32,
+    buffers: [u32; BUFFERS],
+    // buffers the source has played, or never got
+    free: Vec<u32>,
+    samples: Vec<i16>,
+}
+
+impl Speaker {
+    pub fn new(config: &AudioConfig) -> Result<Self, String> {
+        ears::init()?;
+        let mut speaker = Speaker {
+            beeper: Beeper::new(config),
+            source: 0,
+            buffers: [0; BUFFERS],
+            free: Vec::with_capacity(BUFFERS),
+            samples: Vec::with_capacity(SAMPLES_PER_FRAME),
+        };
+        // OpenAL writes as many names as it's asked for to the pointers
+        unsafe {
+            alGenSources(1, &mut speaker.source);
+            alGenBuffers(BUFFERS as i32, speaker.buffers.as_mut_ptr());
+        }
+        check("failed to open the speakers")?;
+        speaker.free.extend_from_slice(&speaker.buffers);
+        Ok(speaker)
+    }
+
+    /// Starts over with a beeper for `config`, e.g. for another rom
+    pub fn set_config(&mut self, config: &AudioConfig) {
+        self.beeper = Beeper::new(config);
+    }
+
+    /// Queues a frame of `tone` without waiting for the speakers. While the queue is full, i.e.
+    /// the emulator is ahead of real time, frames are dropped.
+    pub fn play_frame(&mut self, tone: Tone) -> Result<(), String> {
+        self.reclaim_buffers();
+        if tone == Tone::Silent && self.beeper.is_silent() {
+            // the queue runs dry and the source stops by itself
+            return Ok(());
+        }
+        let buffer = match self.free.pop() {
+            Some(buffer) => buffer,
+            None => return Ok(()),
+        };
+
+        self.samples.clear();
+        self.beeper.render_frame(tone, &mut self.samples);
+        // OpenAL copies the samples, `buffer` is one of ours and not queued
+        unsafe {
+            alBufferData(buffer, AL_FORMAT_MONO16, self.samples.as_ptr() as *const c_void,
+                         (self.samples.len() * 2) as i32, SAMPLE_RATE as i32);
+            alSourceQueueBuffers(self.source, 1, &buffer);
+        }
+        if !self.is_playing() && BUFFERS - self.free.len() >= PREBUFFER {
+            unsafe { alSourcePlay(self.source) };
+        }
+        check("failed to play sound")
+    }
+
+    fn reclaim_buffers(&mut self) {
+        let mut processed = 0;
+        unsafe { alGetSourcei(self.source, AL_BUFFERS_PROCESSED, &mut processed) };
+        for _ in 0..processed {
+            let mut buffer = 0;
+            unsafe { alSourceUnqueueBuffers(self.source, 1, &mut buffer) };
+            self.free.push(buffer);
+        }
+    }
+
+    fn is_playing(&self) -> bool {
+        let mut state = 0;
+        unsafe { alGetSourcei(self.source, AL_SOURCE_STATE, &mut state) };
+        state == AL_PLAYING
+    }
+}
+
+impl Drop for Speaker {
+    fn drop(&mut self) {
+        // stopping unqueues everything, so the buffers can go
+        unsafe {
+            alSourceStop(self.source);
+            alDeleteSources(1, &self.source);
+            alDeleteBuffers(BUFFERS as i32, self.buffers.as_ptr());
+        }
+    }
+}
+
+fn check(message: &str) -> Result<(), String> {
+    match unsafe { alGetError() } {
+        AL_NO_ERROR => Ok(()),
+        error => Err(format!("{}, OpenAL error 0x{:X}", message, error)),
+    }
+}
@@ -0,0 +1,274 @@
+use std::fmt;
+use std::str::FromStr;
+#[cfg(feature = "desktop")]
+use std::io::{self, Stdout, Write};
+#[cfg(feature = "desktop")]
+use std::time::Duration;
+#[cfg(feature = "desktop")]
+use crossterm::{cursor, event, terminal, queue, execute};
+#[cfg(feature = "desktop")]
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+#[cfg(feature = "desktop")]
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+#[cfg(feature = "desktop")]
+use crate::modules::display::Rect;
+#[cfg(feature = "desktop")]
+use crate::modules::input::{self, Key};
+
+/// Characters the terminal renderer draws pixels with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Glyphs {
+    /// ▀ ▄ █, two pixels per character, drawn in the palette's colors
+    HalfBlocks,
+    /// ⠿, eight pixels per character for small terminals
+    Braille,
+}
+
+impl FromStr for Glyphs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-blocks" => Ok(Glyphs::HalfBlocks),
+            "braille" => Ok(Glyphs::Braille),
+            _ => Err(format!("unknown glyphs '{}', expected half-blocks or braille", s)),
+        }
+    }
+}
+
+impl fmt::Display for Glyphs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Glyphs::HalfBlocks => "half-blocks",
+            Glyphs::Braille => "braille",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Draws the screen in the terminal and reads keys from stdin, for running without a window
+/// system, e.g. over ssh. Puts the terminal in raw mode on the alternate screen until dropped.
+#[cfg(feature = "desktop")]
+pub struct Terminal {
+    stdout: Stdout,
+    glyphs: Glyphs,
+    hold_frames: u8,
+    // keys still held and the frames they have left, terminals only report presses
+    held: Vec<(Key, u8)>,
+    // the lines and colors on the terminal, to only redraw the lines that changed
+    drawn: Option<(Vec<String>, u32, u32)>,
+    status: Vec<String>,
+    // most status lines shown at once, to clear the ones no longer shown
+    status_lines: usize,
+    times_rendered: u64,
+    restored: bool,
+}
+
+#[cfg(feature = "desktop")]
+impl Terminal {
+    /// Takes over the terminal. Keys count as held for `hold_frames` after each press (or
+    /// repeat), which should be long enough to bridge the terminal's key repeat delay.
+    pub fn new(glyphs: Glyphs, hold_frames: u8) -> Result<Self, String> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()
+            .and_then(|_| execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide))
+            .map_err(|e| format!("failed to set up the terminal: {}", e))?;
+
+        Ok(Terminal {
+            stdout,
+            glyphs,
+            hold_frames,
+            held: Vec::new(),
+            drawn: None,
+            status: Vec::new(),
+            status_lines: 0,
+            times_rendered: 0,
+            restored: false,
+        })
+    }
+
+    /// Reads the keys pressed since the last frame. Returns the keys held down, and the keys
+    /// pressed this frame.
+    pub fn poll_keys(&mut self) -> Result<(Vec<Key>, Vec<Key>), String> {
+        for (_, frames) in self.held.iter_mut() {
+            *frames -= 1;
+        }
+        self.held.retain(|(_, frames)| *frames > 0);
+
+        let mut pressed = Vec::new();
+        while event::poll(Duration::from_secs(0)).map_err(|e| format!("failed to read input: {}", e))? {
+            match event::read().map_err(|e| format!("failed to read input: {}", e))? {
+                Event::Key(event) => {
+                    if let Some(key) = key_from_event(event) {
+                        self.held.retain(|(held, _)| *held != key);
+                        self.held.push((key, self.hold_frames));
+                        pressed.push(key);
+                    }
+                },
+                Event::Resize(_, _) => self.invalidate(),
+                Event::Mouse(_) => (),
+            }
+        }
+
+        Ok((self.held.iter().map(|(key, _)| *key).collect(), pressed))
+    }
+
+    /// Draws the screen, `width` pixels wide with one byte per pixel like
+    /// `MiniFbDisplay::get_screen`, with the `status` lines from the `Osd` under it. Only the
+    /// lines covering the `dirty` pixels are redrawn, unless the colors changed or something
+    /// else drew over the terminal.
+    pub fn render(&mut self, screen: &[u8], width: usize, foreground: u32, background: u32, dirty: Option<Rect>,
+                  status: &[String]) -> Result<(), String> {
+        let unchanged = match &self.drawn {
+            Some((_, drawn_foreground, drawn_background)) => *drawn_foreground == foreground && *drawn_background == background,
+            None => false,
+        };
+        if unchanged && dirty.is_none() && status == &self.status[..] {
+            return Ok(());
+        }
+
+        let mut lines = draw(screen, width, self.glyphs);
+        let line_width = lines.first().map_or(0, |line| line.chars().count());
+        // blank lines over the status lines no longer shown
+        self.status_lines = self.status_lines.max(status.len());
+        for i in 0..self.status_lines {
+            let text = status.get(i).map_or("", |line| line.as_str());
+            lines.push(format!("{:width$}", text, width = line_width));
+        }
+
+        let stdout = &mut self.stdout;
+        let mut result = match &self.drawn {
+            Some(_) => Ok(()),
+            None => queue!(stdout, ResetColor, terminal::Clear(terminal::ClearType::All)),
+        };
+        result = result.and_then(|_| queue!(stdout, SetForegroundColor(color(foreground)), SetBackgroundColor(color(background))));
+        for (row, line) in lines.iter().enumerate() {
+            let drawn = match &self.drawn {
+                Some((drawn, _, _)) if unchanged => drawn.get(row) == Some(line),
+                _ => false,
+            };
+            if !drawn {
+                result = result.and_then(|_| queue!(stdout, cursor::MoveTo(0, row as u16), Print(line),
+                                                    terminal::Clear(terminal::ClearType::UntilNewLine)));
+            }
+        }
+        result.and_then(|_| queue!(stdout, ResetColor))
+            .map_err(|e| format!("failed to draw to the terminal: {}", e))?;
+        stdout.flush()
+            .map_err(|e| format!("failed to draw to the terminal: {}", e))?;
+
+        self.drawn = Some((lines, foreground, background));
+        self.status = status.to_vec();
+        self.times_rendered += 1;
+        Ok(())
+    }
+
+    pub fn get_times_rendered(&self) -> u64 {
+        self.times_rendered
+    }
+
+    /// Gives the terminal back the way it was found, nothing should be drawn after this
+    pub fn restore(&mut self) {
+        if !self.restored {
+            // nothing to do if restoring fails, the terminal is left as it is
+            let _ = execute!(self.stdout, ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+            let _ = terminal::disable_raw_mode();
+            self.restored = true;
+        }
+    }
+
+    /// Redraws everything next frame, e.g. after something else printed to the terminal
+    pub fn invalidate(&mut self) {
+        self.drawn = None;
+    }
+}
+
+#[cfg(feature = "desktop")]
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+/// The lines of characters `screen` is drawn as
+pub fn draw(screen: &[u8], width: usize, glyphs: Glyphs) -> Vec<String> {
+    let height = screen.len() / width;
+    let lit = |x: usize, y: usize| x < width && y < height && screen[y * width + x] != 0;
+
+    match glyphs {
+        Glyphs::HalfBlocks => (0..height).step_by(2)
+            .map(|y| (0..width)
+                .map(|x| match (lit(x, y), lit(x, y + 1)) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                })
+                .collect())
+            .collect(),
+        Glyphs::Braille => (0..height).step_by(4)
+            .map(|y| (0..width).step_by(2)
+                .map(|x| {
+                    // braille dots are numbered down the left column then the right, with the
+                    // bottom row added later as dots 7 and 8
+                    let dots = [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2), (0, 3), (1, 3)];
+                    let bits = dots.iter()
+                        .enumerate()
+                        .filter(|(_, (dx, dy))| lit(x + dx, y + dy))
+                        .fold(0, |bits, (bit, _)| bits | (1 << bit));
+                    std::char::from_u32(0x2800 + bits).unwrap_or(' ')
+                })
+                .collect())
+            .collect(),
+    }
+}
+
+#[cfg(feature = "desktop")]
+fn color(rgb: u32) -> Color {
+    Color::Rgb {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+    }
+}
+
+/// The window key a terminal key event corresponds to, so the same bindings work in both
+#[cfg(feature = "desktop")]
+fn key_from_event(event: KeyEvent) -> Option<Key> {
+    match event.code {
+        // raw mode swallows ctrl-c, treat it like escape
+        KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => Some(Key::Escape),
+        KeyCode::Char(c) => {
+            let name = match c {
+                ' ' => "Space",
+                ',' => "Comma",
+                '.' => "Period",
+                '/' => "Slash",
+                ';' => "Semicolon",
+                '\'' => "Apostrophe",
+                '-' => "Minus",
+                '=' => "Equal",
+                '[' => "LeftBracket",
+                ']' => "RightBracket",
+                '\\' => "Backslash",
+                _ => return input::key_from_name(&c.to_string()),
+            };
+            input::key_from_name(name)
+        },
+        KeyCode::Esc => Some(Key::Escape),
+        KeyCode::Enter => Some(Key::Enter),
+        KeyCode::Tab => Some(Key::Tab),
+        KeyCode::Backspace => Some(Key::Backspace),
+        KeyCode::Delete => Some(Key::Delete),
+        KeyCode::Up => Some(Key::Up),
+        KeyCode::Down => Some(Key::Down),
+        KeyCode::Left => Some(Key::Left),
+        KeyCode::Right => Some(Key::Right),
+        KeyCode::F(1) => Some(Key::F1),
+        KeyCode::F(8) => Some(Key::F8),
+        KeyCode::F(9) => Some(Key::F9),
+        KeyCode::F(10) => Some(Key::F10),
+        KeyCode::F(12) => Some(Key::F12),
+        _ => None,
+    }
+}
@@ -0,0 +1,55 @@
+#[cfg(test)]
+mod tests {
+    use crate::chip8::analysis::{analyse, Platform};
+    use crate::chip8::cpu::{Cpu, MAX_PROGRAM_SIZE, PROGRAM_COUNTER_START_ADDR};
+
+    #[test]
+    fn test_chip8_program() {
+        // LD V0, 0x01; JP 0x200; followed by a sprite
+        let analysis = analyse(&[0x60, 0x01, 0x12, 0x00, 0xF0, 0x90], PROGRAM_COUNTER_START_ADDR);
+
+        assert_eq!(analysis.platform, Platform::Chip8);
+        assert_eq!(analysis.reachable_bytes, 4);
+        assert!(analysis.invalid_opcodes.is_empty());
+    }
+
+    #[test]
+    fn test_skips_reach_both_branches() {
+        // SE V0, 0x00; JP 0x208; CLS; RET
+        let analysis = analyse(&[0x30, 0x00, 0x12, 0x08, 0x00, 0xE0, 0x00, 0xEE], PROGRAM_COUNTER_START_ADDR);
+
+        assert_eq!(analysis.reachable_bytes, 8);
+    }
+
+    #[test]
+    fn test_detects_extensions() {
+        // HIGH; LD HF, V0
+        let analysis = analyse(&[0x00, 0xFF, 0xF0, 0x30, 0x00, 0xFD], PROGRAM_COUNTER_START_ADDR);
+        assert_eq!(analysis.platform, Platform::SuperChip);
+        assert_eq!(analysis.unsupported_opcodes.len(), 3);
+
+        // LD I, 0x1234 (4 bytes); PLANE 3
+        let analysis = analyse(&[0xF0, 0x00, 0x12, 0x34, 0xF3, 0x01], PROGRAM_COUNTER_START_ADDR);
+        assert_eq!(analysis.platform, Platform::XoChip);
+        assert_eq!(analysis.reachable_bytes, 6);
+
+        // LD AUDIO, [I]; LD PITCH, V0 are XO-CHIP but supported
+        let analysis = analyse(&[0xF0, 0x02, 0xF0, 0x3A], PROGRAM_COUNTER_START_ADDR);
+        assert_eq!(analysis.platform, Platform::XoChip);
+        assert!(analysis.unsupported_opcodes.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_opcodes() {
+        // LD V0, 0x01; invalid
+        let analysis = analyse(&[0x60, 0x01, 0xFF, 0xFF], PROGRAM_COUNTER_START_ADDR);
+
+        assert_eq!(analysis.invalid_opcodes, vec![(0x202, 0xFFFF)]);
+    }
+
+    #[test]
+    fn test_program_size() {
+        assert!(Cpu::check_program(&vec![0; MAX_PROGRAM_SIZE]).is_ok());
+        assert!(Cpu::check_program(&vec![0; MAX_PROGRAM_SIZE + 1]).is_err());
+    }
+}
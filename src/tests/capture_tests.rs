@@ -0,0 +1,14 @@
+#[cfg(test)]
+mod tests {
+    use crate::modules::capture;
+
+    #[test]
+    fn test_pack_1bpp() {
+        let mut screen = vec![0; 16];
+        screen[0] = 1;
+        screen[7] = 1;
+        screen[9] = 1;
+
+        assert_eq!(capture::pack_1bpp(&screen), vec![0b1000_0001, 0b0100_0000]);
+    }
+}
@@ -0,0 +1,154 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::chip8::cpu::Cpu;
+    use crate::chip8::quirks::Quirks;
+    use crate::modules::cheat::{self, Cheat, Comparison, Search};
+    use crate::modules::config::Config;
+    use crate::modules::input::Key;
+    use crate::modules::menu::{Action, Menu, Settings};
+
+    #[test]
+    fn test_search_narrows_down() {
+        let mut memory = vec![3, 3, 7, 0, 9];
+        let mut search = Search::new(&memory);
+        assert_eq!(search.candidates(), &[0, 1, 2, 3, 4]);
+
+        memory[0] = 2;
+        memory[2] = 8;
+        memory[4] = 8;
+        search.narrow(&memory, Comparison::Changed);
+        assert_eq!(search.candidates(), &[0, 2, 4]);
+        assert_eq!(search.previous(0), 2);
+
+        memory[0] = 1;
+        search.narrow(&memory, Comparison::Decreased);
+        assert_eq!(search.candidates(), &[0]);
+
+        let mut search = Search::new(&memory);
+        search.narrow(&memory, Comparison::Equal(8));
+        assert_eq!(search.candidates(), &[2, 4]);
+        memory[4] = 9;
+        search.narrow(&memory, Comparison::Increased);
+        assert_eq!(search.candidates(), &[4]);
+        search.narrow(&memory, Comparison::Unchanged);
+        assert_eq!(search.candidates(), &[4]);
+    }
+
+    #[test]
+    fn test_cheats_file() {
+        let path = std::env::temp_dir().join(format!("crust8cean-cheats-test-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(cheat::read_cheats(&path, "aaaa").unwrap().is_empty());
+
+        let mut lives = Cheat::new(0x3E8, 5);
+        lives.name = "lives".to_string();
+        let mut off = Cheat::new(0x300, 1);
+        off.enabled = false;
+        cheat::write_cheats(&path, "aaaa", &[lives.clone(), off.clone()]).unwrap();
+        cheat::write_cheats(&path, "bbbb", &[Cheat::new(0x200, 0)]).unwrap();
+        assert_eq!(cheat::read_cheats(&path, "aaaa").unwrap(), vec![lives, off]);
+
+        // roms without cheats are dropped from the file
+        cheat::write_cheats(&path, "bbbb", &[]).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("bbbb"));
+
+        fs::write(&path, r#"{"cccc": [{"address": 16, "value": 2}]}"#).unwrap();
+        let cheats = cheat::read_cheats(&path, "cccc").unwrap();
+        assert_eq!(cheats, vec![Cheat::new(0x10, 2)]);
+
+        fs::write(&path, "[").unwrap();
+        assert!(cheat::read_cheats(&path, "cccc").unwrap_err().starts_with("failed to parse"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cheats_freeze_memory_every_frame() {
+        let mut config = Config::default();
+        config.cpu.instructions_per_second = 600;
+        // I = 0x300, then loop adding 1 to V0 and storing it at I
+        let program = vec![0xA3, 0x00, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x02];
+        let mut cpu = Cpu::new_headless(&program, config);
+        let mut disabled = Cheat::new(0x301, 9);
+        disabled.enabled = false;
+        cpu.set_cheats(vec![Cheat::new(0x300, 0x42), disabled]);
+
+        for _ in 0..10 {
+            cpu.run();
+        }
+        assert_eq!(cpu.get_memory()[0x300], 0x42);
+        assert_eq!(cpu.get_memory()[0x301], 0);
+        cpu.run();
+        assert_eq!(cpu.get_memory()[0x300], 0x42);
+        cpu.run();
+        cpu.run();
+        // frozen at the end of frames, the rom can change it in between
+        assert_eq!(cpu.get_memory()[0x300], 4);
+    }
+
+    #[test]
+    fn test_menu_search_and_cheats() {
+        let mut memory = vec![0; 8];
+        memory[5] = 3;
+        let mut menu = Menu::new();
+        menu.open(settings(memory.clone(), Vec::new()));
+        for _ in 0..5 {
+            menu.handle_key(Key::Down);
+        }
+        assert_eq!(menu.lines()[6], "> Cheats");
+        menu.handle_key(Key::Enter);
+        assert_eq!(menu.lines()[1], "> Search memory");
+        menu.handle_key(Key::Enter);
+        assert_eq!(menu.lines()[0], "Search  8 addresses");
+
+        // the search carries on after the menu is closed and the rom runs
+        menu.handle_key(Key::F1);
+        memory[5] = 2;
+        memory[6] = 1;
+        menu.open(settings(memory.clone(), Vec::new()));
+        for _ in 0..5 {
+            menu.handle_key(Key::Down);
+        }
+        menu.handle_key(Key::Enter);
+        menu.handle_key(Key::Enter);
+        menu.handle_key(Key::Down);
+        menu.handle_key(Key::Enter);
+        assert_eq!(menu.lines()[0], "Search  2 addresses");
+        menu.handle_key(Key::Up);
+        menu.handle_key(Key::Right);
+        menu.handle_key(Key::Right);
+        assert_eq!(menu.lines()[1], "> Equal to   < 02 >");
+        menu.handle_key(Key::Enter);
+        assert_eq!(menu.lines()[0], "Search  1 addresses");
+        assert_eq!(menu.lines().last().unwrap(), "  005 = 02");
+
+        // picking an address freezes it at its value
+        for _ in 0..6 {
+            menu.handle_key(Key::Down);
+        }
+        assert_eq!(menu.handle_key(Key::Enter), Some(Action::SetCheats(vec![Cheat::new(5, 2)])));
+        assert_eq!(menu.lines()[2], "> 005 = 02  on");
+        let mut frozen = Cheat::new(5, 3);
+        assert_eq!(menu.handle_key(Key::Right), Some(Action::SetCheats(vec![frozen.clone()])));
+        frozen.enabled = false;
+        assert_eq!(menu.handle_key(Key::Enter), Some(Action::SetCheats(vec![frozen])));
+        assert_eq!(menu.lines()[2], "> 005 = 03  off");
+        assert_eq!(menu.handle_key(Key::Delete), Some(Action::SetCheats(Vec::new())));
+        assert_eq!(menu.lines()[1..], ["> Search memory".to_string()]);
+    }
+
+    fn settings(memory: Vec<u8>, cheats: Vec<Cheat>) -> Settings {
+        Settings {
+            rom_path: None,
+            rom_hash: String::new(),
+            quirks: Quirks::default(),
+            palettes: vec!["white".to_string()],
+            palette: 0,
+            instructions_per_second: 500,
+            scale: 10,
+            keys: Vec::new(),
+            memory,
+            cheats,
+        }
+    }
+}
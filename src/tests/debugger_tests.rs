@@ -0,0 +1,36 @@
+#[cfg(all(test, feature = "desktop"))]
+mod tests {
+    use std::fs;
+    use crate::chip8::cpu::Cpu;
+    use crate::modules::config::Config;
+    use crate::modules::debugger::Command;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Command::parse("screenshot"), Ok(Command::Screenshot));
+        assert_eq!(Command::parse(" s\n"), Ok(Command::Screenshot));
+        assert_eq!(Command::parse("raw"), Ok(Command::Raw));
+        assert!(Command::parse("step").is_err());
+    }
+
+    #[test]
+    fn test_save_raw_screenshot() {
+        let dir = std::env::temp_dir().join(format!("crust8cean-raw-test-{}", std::process::id()));
+        let mut config = Config::default();
+        config.audio.enabled = false;
+        config.capture.dir = dir.to_string_lossy().into_owned();
+        // draws the 0 in the top left
+        let mut cpu = Cpu::new_headless(&[0xD0, 0x05], config);
+        cpu.run();
+
+        let path = cpu.save_raw_screenshot().unwrap();
+        assert_eq!(path.extension().unwrap(), "1bpp");
+        let raw = fs::read(&path).unwrap();
+        assert_eq!(raw.len(), 256);
+        // the top row of the 0, 8 bytes to a row
+        assert_eq!(raw[0], 0xF0);
+        assert_eq!(raw[8], 0x90);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+#[cfg(test)]
+mod tests {
+    use crate::modules::config::{DisplayConfig, FilterConfig};
+    use crate::modules::display::{draw_grid, fit_scale, letterbox, upscale, MiniFbDisplay, Persistence, Rect, SCREEN_WIDTH};
+    use crate::modules::palette::{self, Palette};
+
+    fn display(persistence: Persistence) -> MiniFbDisplay {
+        let config = DisplayConfig {
+            color: Some(0xFF_FFFF),
+            persistence,
+            persistence_frames: 2,
+            ..DisplayConfig::default()
+        };
+        MiniFbDisplay::new(&config, &FilterConfig::default())
+    }
+
+    #[test]
+    fn test_upscale() {
+        let scaled = upscale(&[1, 2], 2, 1, 2);
+
+        assert_eq!(scaled, vec![1, 1, 2, 2,
+                                1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn test_grid() {
+        let mut scaled = upscale(&[1], 1, 1, 3);
+        draw_grid(&mut scaled, 3, 3);
+
+        assert_eq!(scaled[0], 1);
+        assert_eq!(scaled[4], 1);
+        assert_ne!(scaled[2], 1);
+        assert_ne!(scaled[6], 1);
+    }
+
+    #[test]
+    fn test_fit_scale() {
+        assert_eq!(fit_scale(640, 320), 10);
+        // the tighter dimension wins
+        assert_eq!(fit_scale(1000, 320), 10);
+        assert_eq!(fit_scale(10, 10), 1);
+    }
+
+    #[test]
+    fn test_letterbox() {
+        let mut buffer = Vec::new();
+        letterbox(&[1, 1], 2, 1, &mut buffer, 4, 3);
+
+        assert_eq!(buffer, vec![0, 0, 0, 0,
+                                0, 1, 1, 0,
+                                0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_persistence_decay() {
+        let mut display = display(Persistence::Decay);
+        display.write(0, 1);
+        assert_eq!(display.next_frame().pixels[0], 0xFF_FFFF);
+
+        // fades out over 2 frames
+        display.write(0, 0);
+        assert_eq!(display.next_frame().pixels[0], 0x7F_7F7F);
+        assert_eq!(display.next_frame().pixels[0], 0);
+    }
+
+    #[test]
+    fn test_persistence_or() {
+        let mut display = display(Persistence::Or);
+        display.write(0, 1);
+        assert_eq!(display.next_frame().pixels[0], 0xFF_FFFF);
+
+        display.write(0, 0);
+        assert_eq!(display.next_frame().pixels[0], 0xFF_FFFF);
+        assert_eq!(display.next_frame().pixels[0], 0);
+    }
+
+    #[test]
+    fn test_persistence_off() {
+        let mut display = display(Persistence::Off);
+        display.write(0, 1);
+        assert_eq!(display.next_frame().pixels[0], 0xFF_FFFF);
+
+        display.write(0, 0);
+        assert_eq!(display.next_frame().pixels[0], 0);
+    }
+
+    #[test]
+    fn test_palette_from_toml() {
+        let palette = Palette::from_toml("test", "background = \"000080\"\nforeground = 0xFFFF00").unwrap();
+        assert_eq!(palette.background, 0x00_0080);
+        assert_eq!(palette.foreground, 0xFF_FF00);
+        assert_eq!(palette.planes[0], 0x00_0080);
+
+        // the planes come back as written, integers or hex strings
+        let planes = "background = \"000000\"\nforeground = \"FFFFFF\"\nplanes = [\"000000\", 0xFFFFFF, \"#808080\", \"404040\"]";
+        let palette = Palette::from_toml("test", planes).unwrap();
+        assert_eq!(palette.planes, [0x00_0000, 0xFF_FFFF, 0x80_8080, 0x40_4040]);
+
+        let too_few = "background = \"000000\"\nforeground = \"FFFFFF\"\nplanes = [\"000000\", \"FFFFFF\"]";
+        assert!(Palette::from_toml("test", too_few).is_err());
+        let unknown = "background = \"000000\"\nforeground = \"FFFFFF\"\nplane = [\"000000\"]";
+        assert!(Palette::from_toml("test", unknown).is_err());
+    }
+
+    #[test]
+    fn test_bundled_palette_planes() {
+        let amber = palette::builtin().into_iter().find(|palette| palette.name == "amber").unwrap();
+        assert_eq!(amber.planes, [0x1A_0F00, 0xFF_B000, 0x8C_5A00, 0xFF_D980]);
+    }
+
+    #[test]
+    fn test_next_palette() {
+        let mut display = display(Persistence::Off);
+        display.write(0, 1);
+        assert_eq!(display.next_frame().pixels[0], 0xFF_FFFF);
+
+        // the color override only lasts until the palette changes
+        assert_eq!(display.next_palette().name, "red");
+        let frame = display.next_frame();
+        assert_eq!(frame.pixels[0], 0xFF_0000);
+        assert_eq!(frame.pixels[1], 0x00_0000);
+    }
+
+    #[test]
+    fn test_set_palettes() {
+        let mut display = display(Persistence::Off);
+        let theme = Palette::new("theme", 0x10_2030, 0x40_5060);
+        let config = DisplayConfig {
+            palette: "theme".to_string(),
+            ..DisplayConfig::default()
+        };
+        display.set_palettes(vec![Palette::new("white", 0, 0xFF_FFFF), theme], &config).unwrap();
+        display.write(0, 1);
+        let frame = display.next_frame();
+        assert_eq!(frame.pixels[0], 0x40_5060);
+        assert_eq!(frame.pixels[1], 0x10_2030);
+
+        let missing = DisplayConfig {
+            palette: "missing".to_string(),
+            ..DisplayConfig::default()
+        };
+        assert!(display.set_palettes(Vec::new(), &missing).is_err());
+    }
+
+    #[test]
+    fn test_dirty_region() {
+        let mut display = display(Persistence::Off);
+        assert_eq!(display.take_dirty(), None);
+
+        display.write(2 + 3 * SCREEN_WIDTH as usize, 1);
+        display.write(5 + SCREEN_WIDTH as usize, 1);
+        assert_eq!(display.take_dirty(), Some(Rect { x: 2, y: 1, width: 4, height: 3 }));
+        assert_eq!(display.take_dirty(), None);
+
+        // writing what's already there changes nothing
+        display.write(5 + SCREEN_WIDTH as usize, 1);
+        assert_eq!(display.take_dirty(), None);
+
+        display.clear();
+        assert_eq!(display.take_dirty(), Some(Rect { x: 2, y: 1, width: 4, height: 3 }));
+    }
+
+    #[test]
+    fn test_partial_render_matches_full_render() {
+        let config = DisplayConfig {
+            grid: true,
+            ..DisplayConfig::default()
+        };
+        let mut partial = MiniFbDisplay::new(&config, &FilterConfig::default());
+        let mut full = MiniFbDisplay::new(&config, &FilterConfig::default());
+        // a window too wide for the screen, so it's letterboxed
+        let (width, height) = (64 * 4 + 10, 32 * 4);
+        partial.render_to_buffer(width, height, &[]);
+
+        for pos in [0, 70, 300, 2047].iter() {
+            partial.write(*pos, 1);
+            full.write(*pos, 1);
+        }
+        partial.write(70, 0);
+        full.write(70, 0);
+
+        let partial = partial.render_to_buffer(width, height, &[]).unwrap().to_vec();
+        assert_eq!(partial, full.render_to_buffer(width, height, &[]).unwrap());
+        // nothing changed since
+        assert!(full.render_to_buffer(width, height, &[]).is_none());
+    }
+}
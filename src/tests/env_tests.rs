@@ -0,0 +1,127 @@
+#[cfg(test)]
+mod tests {
+    use crate::env::{Encoding, Env, EnvBatch, Reward, ScoreReward};
+    use crate::{Inputs, Options, HEIGHT, WIDTH};
+
+    // draws a random digit, then adds to V1 and stores it as BCD at 0x3F0 while key 4 is held
+    const PROGRAM: [u8; 20] = [0xC2, 0x0F, 0xF2, 0x29, 0xD0, 0x05, 0x64, 0x04, 0xA3, 0xF0,
+        0xE4, 0x9E, 0x12, 0x12, 0x71, 0x01, 0xF1, 0x33, 0x12, 0x0A];
+
+    fn options() -> Options {
+        Options {
+            instructions_per_second: 600,
+            ..Options::default()
+        }
+    }
+
+    fn score(memory: &[u8]) -> f32 {
+        (memory[0x3F0] as u32 * 100 + memory[0x3F1] as u32 * 10 + memory[0x3F2] as u32) as f32
+    }
+
+    #[test]
+    fn test_env_steps() {
+        let mut env = Env::new(&PROGRAM, options(), Box::new(ScoreReward::bcd(0x3F0, 3))).unwrap();
+        let observation = env.reset(7);
+        assert_eq!(observation.len(), WIDTH * HEIGHT);
+
+        let step = env.step(&Inputs::default(), 2);
+        assert_eq!(step.reward, 0.0);
+        assert!(!step.done);
+        assert!(step.observation.iter().any(|pixel| *pixel != 0));
+
+        let step = env.step(&Inputs::with_keys(&[4]), 3);
+        assert!(step.reward > 0.0);
+        assert_eq!(step.reward, score(env.machine().memory()));
+        let first = step;
+
+        // the same seed plays out the same, and the score starts over
+        env.reset(7);
+        env.step(&Inputs::default(), 2);
+        assert_eq!(env.step(&Inputs::with_keys(&[4]), 3), first);
+        let different = (0..16).any(|seed| {
+            env.reset(seed);
+            env.step(&Inputs::default(), 1).observation != first.observation
+        });
+        assert!(different);
+
+        env.reset(7);
+        env.set_max_frames(Some(5));
+        assert!(!env.step(&Inputs::default(), 4).done);
+        assert!(env.step(&Inputs::with_keys(&[4]), 4).done);
+        // nothing runs once the episode is over
+        let score_at_end = score(env.machine().memory());
+        let step = env.step(&Inputs::with_keys(&[4]), 4);
+        assert!(step.done);
+        assert_eq!(step.reward, 0.0);
+        assert_eq!(score(env.machine().memory()), score_at_end);
+
+        // episodes end when the rom stops
+        let mut env = Env::new(&[0x12, 0x00], options(), Box::new(score)).unwrap();
+        assert!(env.step(&Inputs::default(), 10).done);
+    }
+
+    #[test]
+    fn test_score_reward() {
+        let mut memory = vec![0; 8];
+        memory[0..3].copy_from_slice(&[1, 2, 3]);
+        memory[3] = 2;
+        let mut reward = ScoreReward::bcd(0, 3).with_lives(3);
+        reward.reset(&memory);
+        assert_eq!(reward.reward(&memory), 0.0);
+        memory[2] = 9;
+        assert_eq!(reward.reward(&memory), 6.0);
+        memory[1] = 0;
+        assert_eq!(reward.reward(&memory), -20.0);
+        assert!(!reward.done(&memory));
+        memory[3] = 0;
+        assert!(reward.done(&memory));
+
+        let mut word = ScoreReward::new(6, Encoding::Word);
+        word.reset(&memory);
+        memory[6] = 1;
+        assert_eq!(word.reward(&memory), 256.0);
+        let mut byte = ScoreReward::new(7, Encoding::Byte);
+        memory[7] = 200;
+        assert_eq!(byte.reward(&memory), 200.0);
+    }
+
+    #[test]
+    fn test_env_batch() {
+        let rewards: Vec<Box<dyn Reward>> = vec![
+            Box::new(ScoreReward::bcd(0x3F0, 3)),
+            Box::new(ScoreReward::bcd(0x3F0, 3)),
+            Box::new(|memory: &[u8]| memory[0x3F2] as f32),
+        ];
+        let mut batch = EnvBatch::new(&PROGRAM, options(), rewards).unwrap();
+        assert_eq!(batch.len(), 3);
+        let observations = batch.reset(&[1, 2, 1]).unwrap();
+        assert_eq!(observations.len(), 3);
+
+        let actions = [Inputs::with_keys(&[4]), Inputs::default(), Inputs::with_keys(&[4])];
+        let steps = batch.step(&actions, 3).unwrap();
+        assert!(steps[0].reward > 0.0);
+        assert_eq!(steps[1].reward, 0.0);
+        assert_eq!(steps[0].observation, steps[2].observation);
+
+        // each environment plays out the same as one on its own
+        let mut env = Env::new(&PROGRAM, options(), Box::new(ScoreReward::bcd(0x3F0, 3))).unwrap();
+        env.reset(1);
+        assert_eq!(env.step(&actions[0], 3), steps[0]);
+
+        assert!(batch.step(&actions[..1], 3).is_err());
+        assert!(EnvBatch::new(&[0; 4000], options(), vec![Box::new(score)]).is_err());
+    }
+
+    #[test]
+    fn test_env_batch_survives_a_panicking_reward() {
+        let rewards: Vec<Box<dyn Reward>> = vec![
+            Box::new(ScoreReward::bcd(0x3F0, 3)),
+            Box::new(|_: &[u8]| -> f32 { panic!("reward failed") }),
+        ];
+        let mut batch = EnvBatch::new(&PROGRAM, options(), rewards).unwrap();
+        batch.reset(&[1, 1]).unwrap();
+        let actions = [Inputs::default(), Inputs::default()];
+        assert_eq!(batch.step(&actions, 1).unwrap_err(), "environment 1 stopped, its thread panicked");
+        assert!(batch.step(&actions, 1).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+#[cfg(test)]
+mod tests {
+    use crate::modules::config::FilterConfig;
+    use crate::modules::filter::{self, Filter, FilterPipeline, Image};
+
+    fn chain(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_chain() {
+        assert_eq!(filter::parse_chain(&chain(&["epx", "scanlines", "glow"])),
+                   Ok(vec![Filter::Scale2x, Filter::Scanlines, Filter::Glow]));
+        assert!(filter::parse_chain(&chain(&["scanlines", "hq2x"])).is_err());
+        assert!(filter::parse_chain(&chain(&["vhs"])).is_err());
+    }
+
+    #[test]
+    fn test_scale2x_joins_diagonals() {
+        // a diagonal line from top left to bottom right, which scale2x turns into a staircase
+        // instead of two blocks touching at their corners
+        let image = Image {
+            width: 2,
+            height: 2,
+            pixels: vec![1, 0,
+                         0, 1],
+        };
+        let scaled = filter::scale2x(&image);
+
+        assert_eq!(scaled.pixels, vec![1, 1, 0, 0,
+                                       1, 0, 1, 0,
+                                       0, 1, 0, 1,
+                                       0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_scanlines() {
+        let mut image = Image {
+            width: 1,
+            height: 2,
+            pixels: vec![0xFF_FFFF, 0xFF_FFFF],
+        };
+        filter::scanlines(&mut image, 0.5);
+
+        assert_eq!(image.pixels, vec![0xFF_FFFF, 0x7F_7F7F]);
+    }
+
+    #[test]
+    fn test_pipeline_fills_window() {
+        let options = FilterConfig {
+            chain: chain(&["hq2x", "scanlines", "glow", "barrel"]),
+            ..FilterConfig::default()
+        };
+        let mut pipeline = FilterPipeline::new(&options);
+        let screen = Image {
+            width: 64,
+            height: 32,
+            pixels: vec![0xFF_FFFF; 64 * 32],
+        };
+        let image = pipeline.process(screen, 640, 330, true);
+
+        assert_eq!((image.width, image.height), (640, 320));
+        assert_eq!(image.pixels.len(), 640 * 320);
+    }
+}
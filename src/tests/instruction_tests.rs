@@ -2,6 +2,8 @@
 mod tests {
     use crate::chip8::cpu::{Cpu, PROGRAM_COUNTER_START_ADDR};
     use crate::modules::display::BYTES_PER_CHARACTER;
+    use crate::modules::config::Config;
+    use crate::chip8::quirks::Quirks;
 
     #[test]
     fn test_ret() {
@@ -291,6 +293,23 @@ mod tests {
         assert_eq!(cpu.i, 20);
     }
 
+    #[test]
+    fn test_add_i_vx_wraps_and_round_trips() {
+        // I is 16 bits, past 0xFFFF it wraps around
+        let mut cpu = init(vec!(0xF0, 0x1E, 0xF0, 0x1E));
+        cpu.registers[0] = 0x20;
+        cpu.i = 0xFFF0;
+        cpu.run();
+        assert_eq!(cpu.i, 0x10);
+
+        // states keep all of it
+        cpu.i = 0xFF00;
+        cpu.run();
+        let mut restored = init(Vec::new());
+        restored.load_state(&cpu.save_state()).unwrap();
+        assert_eq!(restored.i, 0xFF20);
+    }
+
     #[test]
     fn test_ld_f_vx() {
         let mut cpu = init(vec!(0xF0, 0x29));
@@ -300,7 +319,60 @@ mod tests {
         assert_eq!(cpu.i, 10 * BYTES_PER_CHARACTER as usize);
     }
 
+    #[test]
+    fn test_shr_vx_vy_chip8_quirks() {
+        let mut cpu = init_with_quirks(vec!(0x80, 0x16), Quirks::chip8());
+        cpu.registers[0] = 0xFF;
+        cpu.registers[1] = 0b00000110;
+        cpu.run();
+
+        assert_eq!(cpu.registers[0], 0b00000011);
+        assert_eq!(cpu.registers[0x0F], 0);
+    }
+
+    #[test]
+    fn test_jp_vx_schip_quirks() {
+        let mut cpu = init_with_quirks(vec!(0xB3, 0x00), Quirks::schip());
+        cpu.registers[0] = 1;
+        cpu.registers[3] = 2;
+        cpu.run();
+
+        assert_eq!(cpu.program_counter, 0x300 + 2);
+    }
+
+    #[test]
+    fn test_ld_i_vx_chip8_quirks() {
+        let mut cpu = init_with_quirks(vec!(0xF2, 0x55), Quirks::chip8());
+        cpu.i = 0x300;
+        cpu.run();
+
+        assert_eq!(cpu.i, 0x300 + 3);
+    }
+
+    #[test]
+    fn test_ld_audio_i() {
+        let mut cpu = init(vec!(0xF0, 0x02));
+        cpu.i = 0xFF0;
+        cpu.run();
+        assert!(cpu.is_running());
+
+        // the last pattern that fits ends at 0xFFF, one byte later runs past the end
+        let mut cpu = init(vec!(0xF0, 0x02));
+        cpu.i = 0xFF1;
+        cpu.run();
+        assert!(!cpu.is_running());
+        assert_eq!(cpu.get_error(), Some("audio pattern at 0xFF1 runs past the end of memory"));
+    }
+
     fn init(program: Vec<u8>) -> Cpu {
-        Cpu::new(&program)
+        init_with_quirks(program, Quirks::default())
+    }
+
+    fn init_with_quirks(program: Vec<u8>, quirks: Quirks) -> Cpu {
+        let mut config = Config::default();
+        config.audio.enabled = false;
+        config.cpu.seed = Some(0);
+        config.cpu.quirks = quirks;
+        Cpu::new_headless(&program, config)
     }
 }
\ No newline at end of file
@@ -0,0 +1,129 @@
+#[cfg(all(test, feature = "libretro"))]
+mod tests {
+    use std::ffi::CStr;
+    use std::os::raw::{c_char, c_uint, c_void};
+    use std::sync::Mutex;
+    use std::{ptr, slice};
+    use crate::libretro::*;
+    use crate::SAMPLES_PER_FRAME;
+
+    // waits for a key, draws its digit at (key, key) and beeps for key frames, then loops
+    const PROGRAM: [u8; 12] = [0xF0, 0x0A, 0xF0, 0x29, 0xD0, 0x05, 0xF0, 0x18, 0x71, 0x01, 0x12, 0x08];
+
+    static VARIABLES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    static VIDEO: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+    static AUDIO: Mutex<Vec<i16>> = Mutex::new(Vec::new());
+
+    extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        unsafe {
+            match cmd {
+                16 => {
+                    let mut variable = data as *const Variable;
+                    while !(*variable).key.is_null() {
+                        VARIABLES.lock().unwrap().push(CStr::from_ptr((*variable).value).to_string_lossy().into_owned());
+                        variable = variable.add(1);
+                    }
+                    true
+                },
+                15 => {
+                    let variable = data as *mut Variable;
+                    if CStr::from_ptr((*variable).key).to_bytes() == b"crust8cean_palette" {
+                        (*variable).value = b"green\0".as_ptr() as *const c_char;
+                        return true;
+                    }
+                    false
+                },
+                10 | 11 => true,
+                _ => false,
+            }
+        }
+    }
+
+    extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+        assert_eq!((width, height, pitch), (64, 32, 256));
+        let pixels = unsafe { slice::from_raw_parts(data as *const u32, 64 * 32) };
+        *VIDEO.lock().unwrap() = pixels.to_vec();
+    }
+
+    extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+        *AUDIO.lock().unwrap() = unsafe { slice::from_raw_parts(data, frames * 2) }.to_vec();
+        frames
+    }
+
+    extern "C" fn input_poll() {}
+
+    extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+        // A on the joypad, the 5 key
+        (port == 0 && device == 1 && id == 8) as i16
+    }
+
+    #[test]
+    fn test_libretro_core() {
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+        assert!(VARIABLES.lock().unwrap()[0].starts_with("Quirks (restart); default|"));
+
+        let game = GameInfo {
+            path: ptr::null(),
+            data: PROGRAM.as_ptr() as *const c_void,
+            size: PROGRAM.len(),
+            meta: ptr::null(),
+        };
+        assert!(unsafe { retro_load_game(&game) });
+        let state_size = retro_serialize_size();
+        let mut state = vec![0u8; state_size];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state_size) });
+
+        retro_run();
+        let video = VIDEO.lock().unwrap().clone();
+        // the top row of the 5 in green on black
+        assert_eq!(video[5 * 64 + 4..5 * 64 + 10], [0, 0x00FF00, 0x00FF00, 0x00FF00, 0x00FF00, 0]);
+        let audio = AUDIO.lock().unwrap().clone();
+        assert_eq!(audio.len(), SAMPLES_PER_FRAME * 2);
+        assert!(audio.iter().any(|sample| *sample != 0));
+        assert_eq!(retro_get_memory_size(2), 4096);
+        assert_eq!(unsafe { *(retro_get_memory_data(2) as *const u8).add(0x200) }, 0xF0);
+
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state_size) });
+        assert!(!unsafe { retro_unserialize(state.as_ptr() as *const c_void, 10) });
+        retro_reset();
+        retro_unload_game();
+        assert!(retro_get_memory_data(2).is_null());
+        retro_deinit();
+    }
+
+    #[test]
+    fn test_libretro_core_survives_a_stopped_rom() {
+        // each test thread has a core of its own, this one without a frontend's callbacks
+        let mut info = SystemInfo {
+            library_name: ptr::null(),
+            library_version: ptr::null(),
+            valid_extensions: ptr::null(),
+            need_fullpath: true,
+            block_extract: true,
+        };
+        unsafe { retro_get_system_info(&mut info) };
+        assert_eq!(unsafe { CStr::from_ptr(info.valid_extensions) }.to_bytes(), b"ch8|c8");
+
+        // an unknown opcode
+        let rom = [0xFF, 0xFF];
+        let game = GameInfo {
+            path: ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: ptr::null(),
+        };
+        retro_set_environment(environment);
+        assert!(unsafe { retro_load_game(&game) });
+        retro_run();
+        retro_run();
+        retro_reset();
+        retro_run();
+        assert_eq!(retro_get_memory_size(2), 4096);
+        retro_unload_game();
+    }
+}
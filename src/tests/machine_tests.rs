@@ -0,0 +1,91 @@
+#[cfg(test)]
+mod tests {
+    use crate::{Inputs, Machine, Options, SAMPLES_PER_FRAME, WIDTH};
+    use crate::chip8::cpu::MAX_PROGRAM_SIZE;
+
+    // waits for a key, draws its digit at (key, key) and beeps for key frames, then loops
+    const PROGRAM: [u8; 12] = [0xF0, 0x0A, 0xF0, 0x29, 0xD0, 0x05, 0xF0, 0x18, 0x71, 0x01, 0x12, 0x08];
+
+    fn options() -> Options {
+        Options {
+            seed: Some(1),
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    fn test_machine_runs_frames() {
+        let mut machine = Machine::new(&PROGRAM, options()).unwrap();
+        let output = machine.run_frame(&Inputs::default());
+        assert!(output.running);
+        assert!(!output.screen_changed);
+        assert!(!output.audio_active);
+        assert_eq!(output.samples, vec![0; SAMPLES_PER_FRAME]);
+
+        let output = machine.run_frame(&Inputs::with_keys(&[0x5, 0x10]));
+        assert!(output.screen_changed);
+        assert!(output.audio_active);
+        assert!(machine.audio_active());
+        assert_eq!(output.samples.len(), SAMPLES_PER_FRAME);
+        assert!(output.samples.iter().any(|sample| *sample != 0));
+        // the top row of the 5 is 4 pixels wide
+        let row = &machine.framebuffer()[5 * WIDTH..6 * WIDTH];
+        assert_eq!(row.iter().map(|pixel| (*pixel != 0) as u8).collect::<Vec<u8>>()[4..10], [0, 1, 1, 1, 1, 0]);
+
+        for _ in 0..5 {
+            machine.run_frame(&Inputs::default());
+        }
+        assert!(!machine.audio_active());
+        assert!(!machine.run_frame(&Inputs::default()).screen_changed);
+    }
+
+    #[test]
+    fn test_machine_save_state() {
+        let mut machine = Machine::new(&PROGRAM, options()).unwrap();
+        machine.run_frame(&Inputs::default());
+        let state = machine.save_state();
+        machine.run_frame(&Inputs::with_keys(&[0x3]));
+        let expected = machine.framebuffer().to_vec();
+
+        machine.load_state(&state).unwrap();
+        assert!(machine.framebuffer().iter().all(|pixel| *pixel == 0));
+        machine.run_frame(&Inputs::with_keys(&[0x3]));
+        assert_eq!(machine.framebuffer(), &expected[..]);
+
+        assert!(machine.load_state(&state[..10]).is_err());
+        assert_eq!(machine.framebuffer(), &expected[..]);
+    }
+
+    #[test]
+    fn test_machine_rejects_bad_roms_and_options() {
+        assert!(Machine::new(&vec![0; MAX_PROGRAM_SIZE + 1], options()).is_err());
+        let options = Options {
+            volume: 2.0,
+            ..options()
+        };
+        assert!(Machine::new(&PROGRAM, options).is_err());
+    }
+
+    #[test]
+    fn test_machine_stops_on_errors() {
+        // an unknown opcode
+        let mut machine = Machine::new(&[0xFF, 0xFF], options()).unwrap();
+        assert!(!machine.run_frame(&Inputs::default()).running);
+        assert_eq!(machine.error(), Some("unknown opcode FFFF at 0x200"));
+
+        // reset starts the rom over, error and all
+        machine.reset(Some(1));
+        assert!(machine.is_running());
+        assert_eq!(machine.error(), None);
+
+        // I = 0xFFF, then drawing 2 rows reads past the end of memory
+        let mut machine = Machine::new(&[0xAF, 0xFF, 0xD0, 0x02], options()).unwrap();
+        assert!(!machine.run_frame(&Inputs::default()).running);
+        assert_eq!(machine.error(), Some("read at 0x1000 out of bounds"));
+
+        // returning from a subroutine that was never called
+        let mut machine = Machine::new(&[0x00, 0xEE], options()).unwrap();
+        machine.run_frame(&Inputs::default());
+        assert!(machine.error().unwrap().starts_with("stack underflow"));
+    }
+}
@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::modules::input::Key;
+    use crate::chip8::cpu::{Cpu, STATE_SIZE};
+    use crate::chip8::quirks::Quirks;
+    use crate::modules::config::{self, Config};
+    use crate::modules::menu::{Action, Menu, Settings};
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut config = Config::default();
+        config.audio.enabled = false;
+        config.cpu.seed = Some(42);
+        // V0 = 5, I = 0x300, then loop drawing RND V1 and the font sprite for V0
+        let program = vec![0x60, 0x05, 0xA3, 0x00, 0xC1, 0xFF, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x04];
+        let mut cpu = Cpu::new_headless(&program, config.clone());
+        for _ in 0..3 {
+            cpu.run();
+        }
+        let state = cpu.save_state();
+        assert_eq!(state.len(), STATE_SIZE);
+
+        for _ in 0..10 {
+            cpu.run();
+        }
+        let expected = cpu.save_state();
+
+        let mut restored = Cpu::new_headless(&program, config);
+        restored.load_state(&state).unwrap();
+        for _ in 0..10 {
+            restored.run();
+        }
+        assert_eq!(restored.save_state(), expected);
+
+        assert!(restored.load_state(&state[1..]).is_err());
+    }
+
+    #[test]
+    fn test_menu_navigation() {
+        let mut menu = Menu::new();
+        menu.open(Settings {
+            rom_path: None,
+            rom_hash: String::new(),
+            quirks: Quirks::default(),
+            palettes: vec!["white".to_string(), "amber".to_string()],
+            palette: 0,
+            instructions_per_second: 500,
+            scale: 10,
+            keys: vec!["1".to_string(), "2".to_string()],
+            memory: Vec::new(),
+            cheats: Vec::new(),
+        });
+        assert_eq!(menu.lines()[1], "> Resume");
+
+        // up wraps around to quit
+        assert_eq!(menu.handle_key(Key::Up), None);
+        assert_eq!(menu.lines().last().unwrap(), "> Quit");
+
+        for _ in 0..4 {
+            menu.handle_key(Key::Up);
+        }
+        assert_eq!(menu.handle_key(Key::Right), Some(Action::SetPalette("amber".to_string())));
+        menu.handle_key(Key::Down);
+        assert_eq!(menu.handle_key(Key::Left), Some(Action::SetSpeed(400)));
+
+        // rebinding swaps with the key already bound to it
+        menu.handle_key(Key::Down);
+        menu.handle_key(Key::Down);
+        assert_eq!(menu.handle_key(Key::Enter), None);
+        assert_eq!(menu.handle_key(Key::Enter), None);
+        assert_eq!(menu.lines()[1], "> 0  press a key...");
+        assert_eq!(menu.handle_key(Key::Key2), Some(Action::BindKey(0, Key::Key2)));
+        assert_eq!(&menu.lines()[1..], &["> 0  2".to_string(), "  1  1".to_string()]);
+
+        // escape goes back to the main menu, then closes it
+        menu.handle_key(Key::Escape);
+        assert_eq!(menu.lines()[11], "> Key bindings");
+        assert_eq!(menu.handle_key(Key::Escape), Some(Action::Close));
+        assert!(!menu.is_open());
+        assert!(menu.lines().is_empty());
+    }
+
+    #[test]
+    fn test_states_page() {
+        let mut menu = Menu::new();
+        menu.open(Settings {
+            rom_path: None,
+            rom_hash: "no such rom".to_string(),
+            quirks: Quirks::default(),
+            palettes: vec!["white".to_string()],
+            palette: 0,
+            instructions_per_second: 500,
+            scale: 10,
+            keys: Vec::new(),
+            memory: Vec::new(),
+            cheats: Vec::new(),
+        });
+        for _ in 0..3 {
+            menu.handle_key(Key::Down);
+        }
+        assert_eq!(menu.handle_key(Key::Enter), None);
+        assert_eq!(menu.lines()[0], "Save state");
+        assert_eq!(menu.lines()[1], "> Slot 1  empty");
+        assert_eq!(menu.lines().len(), 9);
+
+        // saving closes the menu
+        menu.handle_key(Key::Down);
+        assert_eq!(menu.handle_key(Key::Enter), Some(Action::SaveState(2)));
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn test_menu_on_a_still_screen() {
+        let mut config = Config::default();
+        config.audio.enabled = false;
+        // counts up in V0 forever, so nothing on the screen ever changes
+        let mut cpu = Cpu::new_headless(&[0x70, 0x01, 0x12, 0x00], config);
+        cpu.run_frame();
+        let cycles = cpu.get_total_cycles();
+
+        cpu.handle_key(Key::F1);
+        for key in [Key::Down, Key::Up].iter() {
+            cpu.run_frame();
+            cpu.handle_key(*key);
+        }
+        cpu.run_frame();
+        assert_eq!(cpu.get_total_cycles(), cycles);
+
+        // back on resume, which closes the menu
+        cpu.handle_key(Key::Enter);
+        cpu.run_frame();
+        assert!(cpu.get_total_cycles() > cycles);
+    }
+
+    #[test]
+    fn test_save_setting() {
+        let path = std::env::temp_dir().join(format!("crust8cean-setting-test-{}.toml", std::process::id()));
+        fs::write(&path, "[display]\ngrid = true\n").unwrap();
+        config::save_setting(&path, "display.scale", toml::Value::Integer(4)).unwrap();
+        config::save_setting(&path, "cpu.quirks", toml::Value::String("schip".to_string())).unwrap();
+
+        let saved: toml::Value = fs::read_to_string(&path).unwrap().parse().unwrap();
+        assert_eq!(saved["display"]["grid"].as_bool(), Some(true));
+        assert_eq!(saved["display"]["scale"].as_integer(), Some(4));
+        assert_eq!(saved["cpu"]["quirks"].as_str(), Some("schip"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
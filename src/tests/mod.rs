@@ -1 +1,16 @@
-mod instruction_tests;
\ No newline at end of file
+mod instruction_tests;
+mod analysis_tests;
+mod display_tests;
+mod filter_tests;
+mod capture_tests;
+mod debugger_tests;
+mod record_tests;
+mod terminal_tests;
+mod osd_tests;
+mod menu_tests;
+mod cheat_tests;
+mod patch_tests;
+mod machine_tests;
+mod env_tests;
+mod libretro_tests;
+mod script_tests;
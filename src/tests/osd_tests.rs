@@ -0,0 +1,57 @@
+#[cfg(test)]
+mod tests {
+    use crate::chip8::cpu::Cpu;
+    use crate::modules::config::Config;
+    use crate::modules::input::Key;
+    use crate::modules::osd::{self, Anchor, Osd, MESSAGE_FRAMES};
+
+    #[test]
+    fn test_messages_expire() {
+        let mut osd = Osd::new(false);
+        osd.message("Palette: amber");
+        osd.set_paused(true);
+        assert_eq!(osd.lines(), vec![(Anchor::TopRight, "PAUSED".to_string()),
+                                     (Anchor::BottomLeft, "Palette: amber".to_string())]);
+
+        for _ in 0..MESSAGE_FRAMES {
+            osd.tick(0);
+        }
+        osd.set_paused(false);
+        assert!(osd.lines().is_empty());
+    }
+
+    #[test]
+    fn test_unpause_on_a_still_screen() {
+        let mut config = Config::default();
+        config.audio.enabled = false;
+        // counts up in V0 forever, so nothing on the screen ever changes
+        let mut cpu = Cpu::new_headless(&[0x70, 0x01, 0x12, 0x00], config);
+        cpu.run_frame();
+        let cycles = cpu.get_total_cycles();
+
+        cpu.handle_key(Key::F8);
+        for _ in 0..3 {
+            cpu.run_frame();
+        }
+        assert_eq!(cpu.get_total_cycles(), cycles);
+
+        cpu.handle_key(Key::F8);
+        cpu.run_frame();
+        assert!(cpu.get_total_cycles() > cycles);
+    }
+
+    #[test]
+    fn test_draw() {
+        let (width, height) = (16, 8);
+        let mut buffer = vec![0x80_8080; width * height];
+        osd::draw(&[(Anchor::TopLeft, "1".to_string())], &mut buffer, width, height);
+
+        // the box is 5 pixels wide and 7 high, with the glyph inside its border
+        assert_eq!(buffer[0], 0x20_2020);
+        assert_eq!(buffer[5], 0x80_8080);
+        assert_eq!(buffer[width * 7], 0x80_8080);
+        // the top of the 1
+        assert_eq!(buffer[width + 1], 0x20_2020);
+        assert_eq!(buffer[width + 2], 0xFF_FFFF);
+    }
+}
@@ -0,0 +1,99 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::modules::patch::{self, crc32};
+
+    #[test]
+    fn test_ips() {
+        let rom = vec![0, 1, 2, 3, 4, 5];
+        let mut ips = b"PATCH".to_vec();
+        // two bytes at 1, a run of four 7s at 4 growing the rom, then truncate to 7 bytes
+        ips.extend_from_slice(&[0, 0, 1, 0, 2, 0xAA, 0xBB]);
+        ips.extend_from_slice(&[0, 0, 4, 0, 0, 0, 4, 7]);
+        ips.extend_from_slice(b"EOF");
+        assert_eq!(patch::apply(&rom, &ips).unwrap(), vec![0, 0xAA, 0xBB, 3, 7, 7, 7, 7]);
+        ips.extend_from_slice(&[0, 0, 7]);
+        assert_eq!(patch::apply(&rom, &ips).unwrap(), vec![0, 0xAA, 0xBB, 3, 7, 7, 7]);
+
+        assert_eq!(patch::apply(&rom, &ips[..ips.len() - 4]).unwrap_err(), "patch is truncated");
+        assert_eq!(patch::apply(&rom, b"NOT A PATCH").unwrap_err(), "not an IPS or BPS patch");
+    }
+
+    #[test]
+    fn test_bps() {
+        let rom = vec![1, 2, 3, 4, 5, 6];
+        let target = vec![1, 2, 3, 9, 9, 4, 5, 6, 9, 9, 4];
+        let mut actions = Vec::new();
+        // source read 3, target read 2 bytes, source copy 3 from offset 3, target copy 3 from offset 3
+        actions.extend(number(2 << 2));
+        actions.extend(number(1 << 2 | 1));
+        actions.extend_from_slice(&[9, 9]);
+        actions.extend(number(2 << 2 | 2));
+        actions.extend(number(3 << 1));
+        actions.extend(number(2 << 2 | 3));
+        actions.extend(number(3 << 1));
+        let bps = bps(&rom, &target, &actions);
+        assert_eq!(patch::apply(&rom, &bps).unwrap(), target);
+
+        // the rom, the patched rom and the patch are all checked
+        assert!(patch::apply(&[1, 2, 3, 4, 5, 7], &bps).unwrap_err().contains("different rom"));
+        let mut wrong_target = target.clone();
+        wrong_target[0] = 0;
+        assert!(patch::apply(&rom, &self::bps(&rom, &wrong_target, &actions)).unwrap_err()
+            .contains("patched rom's checksum"));
+        let mut damaged = bps.clone();
+        damaged[10] ^= 1;
+        assert!(patch::apply(&rom, &damaged).unwrap_err().contains("damaged"));
+
+        // copying from before the start of the rom
+        let mut actions = number(2);
+        actions.extend(number(1 << 1 | 1));
+        assert!(patch::apply(&rom, &self::bps(&rom, &[1], &actions)).unwrap_err().contains("outside the rom"));
+    }
+
+    #[test]
+    fn test_find_patch() {
+        let dir = std::env::temp_dir().join(format!("crust8cean-patch-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.ch8");
+        let archive = dir.join("games.zip");
+        assert_eq!(patch::find_patch(&rom.to_string_lossy()), None);
+
+        fs::write(dir.join("game.bps"), b"").unwrap();
+        assert_eq!(patch::find_patch(&rom.to_string_lossy()), Some(dir.join("game.bps")));
+        fs::write(dir.join("game.ips"), b"").unwrap();
+        assert_eq!(patch::find_patch(&rom.to_string_lossy()), Some(dir.join("game.ips")));
+        // roms in archives are patched from next to the archive
+        fs::write(dir.join("BRIX.ips"), b"").unwrap();
+        assert_eq!(patch::find_patch(&format!("{}:games/BRIX", archive.display())), Some(dir.join("BRIX.ips")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn number(mut n: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let x = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                bytes.push(0x80 | x);
+                return bytes;
+            }
+            bytes.push(x);
+            n -= 1;
+        }
+    }
+
+    fn bps(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut bps = b"BPS1".to_vec();
+        bps.extend(number(source.len()));
+        bps.extend(number(target.len()));
+        bps.extend(number(0));
+        bps.extend_from_slice(actions);
+        bps.extend_from_slice(&crc32(source).to_le_bytes());
+        bps.extend_from_slice(&crc32(target).to_le_bytes());
+        let patch_crc = crc32(&bps);
+        bps.extend_from_slice(&patch_crc.to_le_bytes());
+        bps
+    }
+}
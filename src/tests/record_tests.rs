@@ -0,0 +1,153 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::chip8::cpu::Cpu;
+    use crate::modules::audio::{self, Beeper, Tone, Waveform, SAMPLES_PER_FRAME};
+    use crate::modules::config::{AudioConfig, Config};
+    use crate::modules::filter::Image;
+    use crate::modules::record::{RecordFormat, Recorder};
+
+    #[test]
+    fn test_beeper_renders_a_frame_of_samples() {
+        let mut beeper = Beeper::new(&no_envelope());
+        let mut samples = Vec::new();
+        beeper.render_frame(Tone::Beep, &mut samples);
+        beeper.render_frame(Tone::Silent, &mut samples);
+
+        assert_eq!(samples.len(), SAMPLES_PER_FRAME * 2);
+        assert!(samples[..SAMPLES_PER_FRAME].iter().any(|sample| *sample != 0));
+        assert!(samples[SAMPLES_PER_FRAME..].iter().all(|sample| *sample == 0));
+    }
+
+    #[test]
+    fn test_beeper_plays_patterns() {
+        // half on, half off, at 4000 bits per second the pattern loops at 31.25Hz
+        let mut pattern = [0; audio::PATTERN_SIZE];
+        for byte in pattern.iter_mut().take(audio::PATTERN_SIZE / 2) {
+            *byte = 0xFF;
+        }
+        let mut beeper = Beeper::new(&no_envelope());
+        let mut samples = Vec::new();
+        for _ in 0..2 {
+            beeper.render_frame(Tone::Pattern { pattern, pitch: audio::DEFAULT_PITCH }, &mut samples);
+        }
+
+        // 1411.2 samples per loop, give or take a sample either side of the edges
+        assert!(samples[..705].iter().all(|sample| *sample > 0));
+        assert!(samples[707..1411].iter().all(|sample| *sample < 0));
+        assert!(samples[1413..1420].iter().all(|sample| *sample > 0));
+
+        assert!((audio::pattern_rate(audio::DEFAULT_PITCH + 48) - 8000.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_beeper_envelope() {
+        let config = AudioConfig {
+            waveform: Waveform::Square,
+            attack_ms: 10,
+            release_ms: 20,
+            ..AudioConfig::default()
+        };
+        let mut beeper = Beeper::new(&config);
+        let mut samples = Vec::new();
+        beeper.render_frame(Tone::Beep, &mut samples);
+
+        // 441 samples to fade in, then the square wave at half volume
+        assert!(samples[0].abs() < 100);
+        assert!(samples[220].abs() > 8000 && samples[220].abs() < 8400);
+        assert!(samples[441..].iter().all(|sample| sample.abs() == 16383));
+
+        // 882 samples to fade out after the sound timer stops
+        samples.clear();
+        beeper.render_frame(Tone::Silent, &mut samples);
+        assert!(!beeper.is_silent());
+        beeper.render_frame(Tone::Silent, &mut samples);
+        assert!(beeper.is_silent());
+        assert!(samples[870] != 0);
+        assert!(samples[882..].iter().all(|sample| *sample == 0));
+    }
+
+    #[test]
+    fn test_waveforms() {
+        for waveform in [Waveform::Square, Waveform::Triangle, Waveform::Sine, Waveform::Noise].iter() {
+            let config = AudioConfig {
+                waveform: *waveform,
+                frequency: 441.0,
+                ..no_envelope()
+            };
+            let mut samples = Vec::new();
+            Beeper::new(&config).render_frame(Tone::Beep, &mut samples);
+
+            let peak = samples.iter().map(|sample| (*sample as i32).abs()).max().unwrap();
+            assert!(peak > 16000 && peak <= 16384, "{} peaks at {}", waveform, peak);
+            if *waveform != Waveform::Noise {
+                // 100 samples per period
+                assert_eq!(samples[25] > 0, samples[125] > 0, "{}", waveform);
+                assert_eq!(samples[75] < 0, samples[175] < 0, "{}", waveform);
+            }
+        }
+    }
+
+    #[test]
+    fn test_record_y4m() {
+        let dir = std::env::temp_dir().join(format!("crust8cean-record-test-{}", std::process::id()));
+        let mut recorder = Recorder::start(&dir, RecordFormat::Y4m, 2, 1, &AudioConfig::default()).unwrap();
+        for _ in 0..3 {
+            let image = Image { width: 2, height: 1, pixels: vec![0x000000, 0xFFFFFF] };
+            recorder.add_frame(image, Tone::Silent).unwrap();
+        }
+        let (video, audio) = recorder.finish().unwrap();
+
+        let data = fs::read(&video).unwrap();
+        let header = b"YUV4MPEG2 W2 H1 F60:1 Ip A1:1 C444 XCOLORRANGE=FULL\n";
+        assert!(data.starts_with(header));
+        let frame = b"FRAME\n\x00\xFF\x80\x80\x80\x80";
+        assert_eq!(data.len(), header.len() + frame.len() * 3);
+        assert_eq!(&data[header.len()..header.len() + frame.len()], &frame[..]);
+
+        let reader = hound::WavReader::open(&audio).unwrap();
+        assert_eq!(reader.duration() as usize, SAMPLES_PER_FRAME * 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wav_follows_emulated_frames() {
+        let mut config = Config {
+            audio: no_envelope(),
+            ..Config::default()
+        };
+        config.cpu.instructions_per_second = 600;
+        // V0 = 5, sound timer = V0, then loop adding to V1 forever
+        let program = vec![0x60, 0x05, 0xF0, 0x18, 0x71, 0x01, 0x12, 0x04];
+        let path = std::env::temp_dir().join(format!("crust8cean-wav-test-{}.wav", std::process::id()));
+        let mut cpu = Cpu::new_headless(&program, config);
+        cpu.start_wav(&path).unwrap();
+        // 10 instructions a frame, the last one unfinished
+        for _ in 0..105 {
+            cpu.run();
+        }
+        let (wav, frames) = cpu.finish_wav().unwrap().unwrap();
+        assert_eq!(frames, 10);
+
+        let samples: Vec<i16> = hound::WavReader::open(&wav).unwrap()
+            .into_samples().map(|sample| sample.unwrap()).collect();
+        assert_eq!(samples.len(), SAMPLES_PER_FRAME * 10);
+        // the timer is set during the first frame and counts down at the start of the next five
+        let beeping: Vec<bool> = samples.chunks(SAMPLES_PER_FRAME)
+            .map(|frame| frame.iter().any(|&sample| sample != 0))
+            .collect();
+        assert_eq!(beeping, vec![true, true, true, true, true, true, false, false, false, false]);
+        assert!(cpu.finish_wav().unwrap().is_none());
+
+        fs::remove_file(&wav).unwrap();
+    }
+
+    fn no_envelope() -> AudioConfig {
+        AudioConfig {
+            attack_ms: 0,
+            release_ms: 0,
+            ..AudioConfig::default()
+        }
+    }
+}
@@ -0,0 +1,97 @@
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::chip8::cpu::Cpu;
+    use crate::modules::config::Config;
+    use crate::modules::script::{Script, ScriptView};
+
+    // V0 = 5, I = 0x300, store V0 at I, then loop adding to V1 forever
+    const PROGRAM: [u8; 10] = [0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x71, 0x01, 0x12, 0x06];
+
+    fn write_script(name: &str, source: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("crust8cean-{}-{}.rhai", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    fn cpu() -> Cpu {
+        let mut config = Config::default();
+        config.cpu.instructions_per_second = 600;
+        Cpu::new_headless(&PROGRAM, config)
+    }
+
+    #[test]
+    fn test_script_hooks() {
+        let path = write_script("hooks", r#"
+            on_write(0x300, |address, value| poke(address + 1, value * 2));
+            on_pc(0x206, || set_reg(2, reg(2) + 1));
+            on_frame(|| if frame() == 3 { stop(); });
+            set_reg(3, 7);
+        "#);
+        let mut cpu = cpu();
+        cpu.load_script(&path).unwrap();
+        assert_eq!(cpu.registers[3], 7);
+        for _ in 0..100 {
+            cpu.run();
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert!(cpu.take_script_error().is_none());
+        // stopped at the end of the third frame of 10 instructions
+        assert!(!cpu.is_running());
+        assert_eq!(cpu.get_total_cycles(), 30);
+        assert_eq!(cpu.get_memory()[0x300], 5);
+        assert_eq!(cpu.get_memory()[0x301], 10);
+        // the hook ran before every add
+        assert_eq!(cpu.registers[2], cpu.registers[1]);
+        assert!(cpu.registers[1] > 0);
+    }
+
+    #[test]
+    fn test_script_input_and_overlay() {
+        let path = write_script("input", r#"
+            on_frame(|| {
+                if frame() == 2 { press(0xA); release(0xA); press(4); }
+                overlay(`frame ${frame()}`);
+            });
+        "#);
+        let mut script = Script::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let view = ScriptView { memory: vec![0; 0x1000], ..ScriptView::default() };
+        assert!(script.run(view.clone()).unwrap().is_none());
+
+        assert!(script.frame_end(view.clone()).unwrap().is_none());
+        assert_eq!(script.get_keys(), [false; 16]);
+        assert_eq!(script.take_overlay(), vec!["frame 1".to_string()]);
+        assert!(script.take_overlay().is_empty());
+
+        script.frame_end(view).unwrap();
+        let mut keys = [false; 16];
+        keys[4] = true;
+        assert_eq!(script.get_keys(), keys);
+        assert_eq!(script.take_overlay(), vec!["frame 2".to_string()]);
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let path = write_script("throws", r#"on_frame(|| if frame() == 2 { throw "score too low"; });"#);
+        let mut cpu = cpu();
+        cpu.load_script(&path).unwrap();
+        for _ in 0..30 {
+            cpu.run();
+        }
+        let error = cpu.take_script_error().unwrap();
+        assert!(error.contains("score too low"), "{}", error);
+        // the script is dropped, the rom carries on
+        assert!(cpu.is_running());
+        assert_eq!(cpu.get_total_cycles(), 30);
+        assert!(cpu.take_script_error().is_none());
+
+        fs::write(&path, "poke(0x1000, 1);").unwrap();
+        assert!(cpu.load_script(&path).unwrap_err().contains("outside memory"));
+        fs::write(&path, "on_frame(|| {").unwrap();
+        assert!(cpu.load_script(&path).unwrap_err().starts_with("failed to load script"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,23 @@
+#[cfg(test)]
+mod tests {
+    use crate::modules::terminal::{draw, Glyphs};
+
+    #[test]
+    fn test_draw_half_blocks() {
+        let screen = [1, 0, 1, 0,
+                      1, 1, 0, 0];
+
+        assert_eq!(draw(&screen, 4, Glyphs::HalfBlocks), vec!["█▄▀ ".to_string()]);
+    }
+
+    #[test]
+    fn test_draw_braille() {
+        // dots 1, 5 and 8
+        let screen = [1, 0, 0, 0,
+                      0, 1, 0, 0,
+                      0, 0, 0, 0,
+                      0, 1, 0, 0];
+
+        assert_eq!(draw(&screen, 4, Glyphs::Braille), vec!["\u{2891}\u{2800}".to_string()]);
+    }
+}
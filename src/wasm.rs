@@ -0,0 +1,91 @@
+//! `Machine` for JavaScript, built with `--no-default-features --features wasm` for
+//! `wasm32-unknown-unknown` and bound with `wasm-bindgen`. See web/ for a page using it.
+
+use wasm_bindgen::prelude::*;
+use crate::chip8::quirks::Quirks;
+use crate::machine::{Inputs, Machine, Options, KEYS};
+
+/// A machine and the keys held on it, since JavaScript hands keys over one event at a time
+#[wasm_bindgen]
+pub struct WebMachine {
+    machine: Machine,
+    inputs: Inputs,
+    samples: Vec<f32>,
+    running: bool,
+}
+
+#[wasm_bindgen]
+impl WebMachine {
+    /// Loads `rom` with the `quirks` preset (default, chip8 or schip). Browsers have no
+    /// randomness wasm can reach, so RND is seeded with `seed`, e.g. from `Math.random()`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8], quirks: &str, instructions_per_second: u32, seed: u32) -> Result<WebMachine, JsValue> {
+        let quirks = Quirks::preset(quirks)
+            .ok_or_else(|| JsValue::from_str(&format!("unknown quirks '{}', expected default/chip8/schip", quirks)))?;
+        let options = Options {
+            quirks,
+            instructions_per_second,
+            seed: Some(seed as u64),
+            ..Options::default()
+        };
+        Ok(WebMachine {
+            machine: Machine::new(rom, options).map_err(|e| JsValue::from_str(&e))?,
+            inputs: Inputs::default(),
+            samples: Vec::new(),
+            running: true,
+        })
+    }
+
+    /// Holds or releases CHIP-8 key `key`, 0 to 15
+    #[wasm_bindgen(js_name = setKey)]
+    pub fn set_key(&mut self, key: u8, down: bool) {
+        if (key as usize) < KEYS {
+            self.inputs.keys[key as usize] = down;
+        }
+    }
+
+    /// Runs a frame, call it 60 times a second. Returns whether the screen changed.
+    #[wasm_bindgen(js_name = runFrame)]
+    pub fn run_frame(&mut self) -> bool {
+        let output = self.machine.run_frame(&self.inputs);
+        // Web Audio wants samples from -1 to 1
+        self.samples = output.samples.iter().map(|sample| *sample as f32 / 32768.0).collect();
+        self.running = output.running;
+        output.screen_changed
+    }
+
+    /// 64x32 bytes, 0 for off
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.machine.framebuffer().to_vec()
+    }
+
+    /// The last frame's samples of the beeper at `sampleRate()`
+    pub fn samples(&self) -> Vec<f32> {
+        self.samples.clone()
+    }
+
+    #[wasm_bindgen(js_name = audioActive)]
+    pub fn audio_active(&self) -> bool {
+        self.machine.audio_active()
+    }
+
+    /// Whether the rom is still going, false once it has stopped
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    #[wasm_bindgen(js_name = saveState)]
+    pub fn save_state(&self) -> Vec<u8> {
+        self.machine.save_state()
+    }
+
+    #[wasm_bindgen(js_name = loadState)]
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), JsValue> {
+        self.machine.load_state(state).map_err(|e| JsValue::from_str(&e))
+    }
+}
+
+#[wasm_bindgen(js_name = sampleRate)]
+pub fn sample_rate() -> u32 {
+    crate::machine::SAMPLE_RATE
+}
@@ -0,0 +1,120 @@
+import init, { WebMachine, sampleRate } from './pkg/crust8cean.js';
+
+// the desktop build's default bindings, CHIP-8 key 0 to F
+const KEYS = ['1', '2', '3', '4', 'q', 'w', 'e', 'r', 'a', 's', 'd', 'f', 'z', 'x', 'c', 'v'];
+const FRAME_MS = 1000 / 60;
+const FOREGROUND = [0xff, 0xff, 0xff];
+const BACKGROUND = [0x00, 0x00, 0x00];
+
+const canvas = document.getElementById('screen');
+const context = canvas.getContext('2d');
+const image = context.createImageData(canvas.width, canvas.height);
+const error = document.getElementById('error');
+
+let machine = null;
+let rom = null;
+let state = null;
+let audio = null;
+// when the next frame of samples should start playing
+let audioTime = 0;
+
+function start() {
+  if (!rom) {
+    return;
+  }
+  try {
+    const quirks = document.getElementById('quirks').value;
+    const ips = parseInt(document.getElementById('ips').value, 10) || 500;
+    machine = new WebMachine(rom, quirks, ips, Math.floor(Math.random() * 0xffffffff));
+    state = null;
+    error.textContent = '';
+  } catch (e) {
+    machine = null;
+    error.textContent = e;
+  }
+}
+
+function draw() {
+  const pixels = machine.framebuffer();
+  for (let i = 0; i < pixels.length; i++) {
+    const color = pixels[i] ? FOREGROUND : BACKGROUND;
+    image.data.set(color, i * 4);
+    image.data[i * 4 + 3] = 0xff;
+  }
+  context.putImageData(image, 0, 0);
+}
+
+function play(samples) {
+  // browsers only allow sound after the page has been interacted with
+  if (!audio || !samples.some((sample) => sample !== 0)) {
+    return;
+  }
+  const buffer = audio.createBuffer(1, samples.length, sampleRate());
+  buffer.copyToChannel(samples, 0);
+  const source = audio.createBufferSource();
+  source.buffer = buffer;
+  source.connect(audio.destination);
+  audioTime = Math.max(audioTime, audio.currentTime);
+  source.start(audioTime);
+  audioTime += buffer.duration;
+}
+
+let last = performance.now();
+let behind = 0;
+function loop(now) {
+  behind = Math.min(behind + now - last, FRAME_MS * 4);
+  last = now;
+  while (machine && machine.running() && behind >= FRAME_MS) {
+    if (machine.runFrame()) {
+      draw();
+    }
+    play(machine.samples());
+    behind -= FRAME_MS;
+  }
+  requestAnimationFrame(loop);
+}
+
+function startAudio() {
+  if (!audio) {
+    audio = new AudioContext();
+  }
+}
+
+function setKey(event, down) {
+  startAudio();
+  if (!machine) {
+    return;
+  }
+  if (down && event.key === 'F5') {
+    state = machine.saveState();
+  } else if (down && event.key === 'F7' && state) {
+    machine.loadState(state);
+    draw();
+  } else {
+    const key = KEYS.indexOf(event.key.toLowerCase());
+    if (key < 0) {
+      return;
+    }
+    machine.setKey(key, down);
+  }
+  event.preventDefault();
+}
+
+document.addEventListener('keydown', (event) => setKey(event, true));
+document.addEventListener('keyup', (event) => setKey(event, false));
+document.getElementById('rom').addEventListener('change', async (event) => {
+  startAudio();
+  rom = new Uint8Array(await event.target.files[0].arrayBuffer());
+  start();
+});
+document.getElementById('quirks').addEventListener('change', start);
+document.getElementById('ips').addEventListener('change', start);
+
+await init();
+const url = new URLSearchParams(location.search).get('rom');
+if (url) {
+  const response = await fetch(url);
+  rom = new Uint8Array(await response.arrayBuffer());
+  start();
+}
+requestAnimationFrame(loop);
@@ -0,0 +1,31 @@
+// Runs the wasm build headless in node, after building web/pkg (see the README):
+//   node web/test.mjs
+import assert from 'node:assert/strict';
+import { readFile } from 'node:fs/promises';
+import { initSync, WebMachine, sampleRate } from './pkg/crust8cean.js';
+
+initSync({ module: await readFile(new URL('./pkg/crust8cean_bg.wasm', import.meta.url)) });
+
+// waits for a key, draws its digit at (key, key) and beeps for key frames, then loops
+const rom = new Uint8Array([0xF0, 0x0A, 0xF0, 0x29, 0xD0, 0x05, 0xF0, 0x18, 0x71, 0x01, 0x12, 0x08]);
+const machine = new WebMachine(rom, 'default', 500, 1);
+
+assert.equal(machine.runFrame(), false);
+const state = machine.saveState();
+
+machine.setKey(5, true);
+assert.equal(machine.runFrame(), true);
+assert.ok(machine.audioActive());
+assert.equal(machine.samples().length, sampleRate() / 60);
+assert.ok(machine.samples().some((sample) => sample !== 0));
+// the top row of the 5 is 4 pixels wide
+assert.deepEqual([...machine.framebuffer().slice(5 * 64 + 4, 5 * 64 + 10)].map((pixel) => pixel !== 0),
+                 [false, true, true, true, true, false]);
+
+machine.loadState(state);
+assert.ok(machine.framebuffer().every((pixel) => pixel === 0));
+assert.throws(() => machine.loadState(state.slice(1)));
+assert.throws(() => new WebMachine(rom, 'nope', 500, 1));
+assert.ok(machine.running());
+
+console.log('ok');